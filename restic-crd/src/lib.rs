@@ -1,14 +1,24 @@
+use std::collections::BTreeMap;
+
 use bon::Builder;
 use k8s_openapi::{
     api::core::v1::{
-        Affinity, EnvFromSource, EnvVar, ResourceRequirements, SecretKeySelector, SecurityContext,
-        Volume, VolumeMount,
+        Affinity, ConfigMapKeySelector, Container, EmptyDirVolumeSource, EnvFromSource, EnvVar,
+        LocalObjectReference, PersistentVolumeClaimVolumeSource, PodDNSConfig, PodSecurityContext,
+        ResourceRequirements, SecretKeySelector, SecurityContext, Toleration,
+        TopologySpreadConstraint, Volume, VolumeMount,
+    },
+    apimachinery::pkg::{
+        api::resource::Quantity,
+        apis::meta::v1::{Condition, LabelSelector, Time},
     },
-    apimachinery::pkg::apis::meta::v1::Time,
 };
 use kube::CustomResource;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub mod v1beta1;
+mod validation;
 
 #[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema, Builder)]
 #[kube(
@@ -27,18 +37,26 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 pub struct ScheduledBackupSpec {
     /// The schedule in Cron format, see https://en.wikipedia.org/wiki/Cron.
+    ///
+    /// A field can be `H` to pick a value from a hash of the backup's namespace and name, which spreads backups with the same schedule over time. `H(a-b)` limits the value to a range, and `H/n` runs every `n` units from a hashed offset. For example, `H H(1-4) * * *` runs once a day at a fixed time between 1:00 and 4:59.
     pub schedule: String,
     /// The backup spec
     pub backup: BackupSpec,
     /// Specifies how to treat concurrent executions of a Job. Valid values are:
     ///
     /// - "Allow" (default): allows CronJobs to run concurrently; - "Forbid": forbids concurrent runs, skipping next run if previous run hasn't finished yet; - "Replace": cancels currently running job and replaces it with a new one
-    pub concurrency_policy: Option<String>,
+    pub concurrency_policy: Option<ConcurrencyPolicy>,
     /// The number of failed finished jobs to retain. Value must be non-negative integer. Defaults to 1.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
     pub failed_jobs_history_limit: Option<i32>,
     /// Optional deadline in seconds for starting the job if it misses scheduled time for any reason.  Missed jobs executions will be counted as failed ones.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i64")]
     pub starting_deadline_seconds: Option<i64>,
     /// The number of successful finished jobs to retain. Value must be non-negative integer. Defaults to 3.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
     pub successful_jobs_history_limit: Option<i32>,
     /// This flag tells the controller to suspend subsequent executions, it does not apply to already started executions.  Defaults to false.
     pub suspend: Option<bool>,
@@ -46,12 +64,26 @@ pub struct ScheduledBackupSpec {
     pub time_zone: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ScheduledBackupStatus {
     pub config_map: Option<String>,
     pub cron_job: Option<String>,
     pub last_schedule_time: Option<Time>,
+    /// The schedule of the CronJobs, with `H` tokens resolved
+    pub schedule: Option<String>,
+    /// Per-replica resources when backing up a StatefulSet
+    pub replicas: Option<Vec<ReplicaStatus>>,
+    /// Value of the last handled `restic.anshulg.com/run-now` annotation
+    pub run_now: Option<String>,
+    /// Jobs created for the last handled `restic.anshulg.com/run-now` annotation
+    pub manual_jobs: Option<Vec<String>>,
+    /// Position in the operator's job queue of the next job waiting to start
+    pub queue_position: Option<u32>,
+    /// Results of copying the snapshots of the last run to each secondary repository
+    pub copies: Option<Vec<CopyStatus>>,
+    /// The latest observations of the backup's state. The `Ready` condition is false with reason `InvalidSchedule` if the schedule or time zone is invalid, and with reason `InvalidExtraConfig` if `resticProfile.extraConfigFrom` cannot be read.
+    pub conditions: Option<Vec<Condition>>,
 }
 
 #[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema, Builder)]
@@ -78,6 +110,56 @@ pub struct BackupSpec {
 
     /// Volume Backup
     pub volume: Option<VolumeBackup>,
+
+    /// StatefulSet Backup. Each replica's volume is backed up in a separate run.
+    pub stateful_set: Option<StatefulSetBackup>,
+
+    /// Workload to scale down while the backup runs
+    pub quiesce: Option<Quiesce>,
+
+    /// Kubernetes resources to backup as YAML manifests, in a separate snapshot tagged
+    /// `manifests`. The manifests are passed to the backup pods in a Secret, so they must not
+    /// exceed 1 MiB. The operator re-collects them at most every 5 minutes, so the manifests of a
+    /// scheduled run can be up to 5 minutes older than the run.
+    pub resources: Option<ResourceBackup>,
+
+    /// Specifies the number of retries before marking the backup job failed. Defaults to 6
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub backoff_limit: Option<i32>,
+
+    /// Specifies the duration in seconds relative to the startTime that the backup job may be
+    /// continuously active before the system tries to terminate it; value must be positive
+    /// integer.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::positive_i64")]
+    pub active_deadline_seconds: Option<i64>,
+
+    /// Limits the lifetime of a backup job that has finished execution (either Complete or
+    /// Failed). If set, the job is eligible to be automatically deleted this many seconds after
+    /// it finishes.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub ttl_seconds_after_finished: Option<i32>,
+
+    /// How failed backup pods are handled, by restic exit code. Pods run with
+    /// `restartPolicy: Never` when this is set, as required by Kubernetes.
+    pub pod_failure_policy: Option<PodFailurePolicy>,
+
+    /// Labels and annotations added to the backup pods
+    pub pod_metadata: Option<PodMetadata>,
+
+    /// Labels added to every resource created for the backup, including the pods
+    pub common_labels: Option<BTreeMap<String, String>>,
+
+    /// Annotations added to every resource created for the backup, including the pods
+    pub common_annotations: Option<BTreeMap<String, String>>,
+
+    /// Restic cache kept between backup runs, so the repository index is not downloaded again
+    /// on every run
+    #[serde(default)]
+    #[schemars(schema_with = "validation::cache")]
+    pub cache: Option<Cache>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
@@ -87,6 +169,24 @@ pub struct BackupStatus {
     pub phase: BackupPhase,
     pub config_map: Option<String>,
     pub job: Option<String>,
+    /// Per-replica results when backing up a StatefulSet
+    pub replicas: Option<Vec<ReplicaStatus>>,
+    /// Position in the operator's job queue of the next job waiting to start
+    pub queue_position: Option<u32>,
+    /// Results of copying the snapshots to each secondary repository
+    pub copies: Option<Vec<CopyStatus>>,
+    /// The latest observations of the backup's state. The `Ready` condition is false with reason `InvalidExtraConfig` if `resticProfile.extraConfigFrom` cannot be read, and with reason `QuiesceTimeout` if the job was deleted because the workload was not quiesced in time.
+    pub conditions: Option<Vec<Condition>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyStatus {
+    /// Name of the secondary repository
+    pub name: String,
+    #[serde(default)]
+    #[builder(default)]
+    pub phase: BackupPhase,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Default)]
@@ -95,6 +195,80 @@ pub enum BackupPhase {
     Pending,
     Running,
     Completed,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicaStatus {
+    /// Ordinal of the StatefulSet replica
+    pub ordinal: i32,
+    /// Name of the replica's PersistentVolumeClaim
+    pub claim_name: String,
+    pub config_map: Option<String>,
+    pub job: Option<String>,
+    pub cron_job: Option<String>,
+    #[serde(default)]
+    #[builder(default)]
+    pub phase: BackupPhase,
+}
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema, Builder)]
+#[kube(
+    group = "restic.anshulg.com",
+    version = "v1alpha1",
+    kind = "NodeBackup",
+    plural = "node-backups",
+    derive = "PartialEq",
+    status = "NodeBackupStatus",
+    shortname = "rnb",
+    category = "restic",
+    printcolumn = r#"{"name": "Age", "type": "date", "jsonPath": ".metadata.creationTimestamp"}"#,
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeBackupSpec {
+    /// Host paths to backup on every node. Each path is mounted read-only under `/host`, which
+    /// is the default `volume.pathPrefix`, so `/etc` is stored as `etc` in the snapshots.
+    pub paths: Vec<String>,
+    /// Labels a node must have to be backed up. Every node is backed up if unset.
+    pub node_selector: Option<BTreeMap<String, String>>,
+    /// The schedule in Cron format, see https://en.wikipedia.org/wiki/Cron. `H` tokens are supported like in ScheduledBackups. If unset, every node is backed up once.
+    pub schedule: Option<String>,
+    /// The time zone name for the given schedule, see https://en.wikipedia.org/wiki/List_of_tz_database_time_zones. If not specified, this will default to the time zone of the kube-controller-manager process.
+    pub time_zone: Option<String>,
+    /// Specifies how to treat concurrent executions of a scheduled Job. Defaults to "Allow".
+    pub concurrency_policy: Option<ConcurrencyPolicy>,
+    /// This flag tells the controller to suspend subsequent scheduled executions, it does not apply to already started executions.  Defaults to false.
+    pub suspend: Option<bool>,
+    /// The backup spec. `statefulSet`, `quiesce` and `resources` are ignored, restic runs as root
+    /// unless `resticProfile.needsRootAccess` is false, and the snapshot host defaults to the node
+    /// name.
+    pub backup: BackupSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeBackupStatus {
+    /// Per-node resources of the nodes being backed up
+    pub nodes: Option<Vec<NodeStatus>>,
+    /// The latest observations of the backup's state. The `Ready` condition is false with reason `InvalidSchedule` if the schedule or time zone is invalid.
+    pub conditions: Option<Vec<Condition>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatus {
+    /// Name of the node
+    pub node_name: String,
+    pub config_map: Option<String>,
+    pub job: Option<String>,
+    pub cron_job: Option<String>,
+    #[serde(default)]
+    #[builder(default)]
+    pub phase: BackupPhase,
+    /// Results of copying the snapshots of the last run to each secondary repository
+    pub copies: Option<Vec<CopyStatus>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
@@ -109,15 +283,86 @@ pub struct ResticConfig {
     /// Set target pack size in MiB, created pack files may be larger
     pub pack_size: Option<u64>,
     /// Retention policy
+    #[serde(default)]
+    #[schemars(schema_with = "validation::retention")]
     pub retention: Option<Retention>,
     /// Backup Options
     pub backup: Option<BackupOptions>,
+    /// Additional profiles, each backed up in its own snapshots in the same run
+    pub profiles: Option<Vec<BackupProfile>>,
+    /// resticprofile groups by name, each a list of profile names. The job runs every profile by
+    /// default; a group can be run instead with `resticProfile.args`, e.g.
+    /// `["--name", "media", "backup"]`.
+    pub groups: Option<BTreeMap<String, Vec<String>>>,
+    /// Repositories the backup's snapshots are copied to after each successful backup, e.g. for
+    /// an offsite copy. Each copy runs in its own container once the backup has finished, so
+    /// the backup runs as an init container and `resticProfile.extraContainers` cannot be used.
+    /// Sidecars must be init containers with `restartPolicy: Always` instead.
+    pub secondary_repositories: Option<Vec<SecondaryRepository>>,
+}
+
+/// Repository that snapshots are copied to with `restic copy`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondaryRepository {
+    /// Name of the repository, used in the copy container name and the status. Must be a DNS
+    /// label of at most 51 characters.
+    #[schemars(schema_with = "validation::secondary_repository_name")]
+    pub name: String,
+    /// Repository Type
+    #[serde(default)]
+    #[builder(default)]
+    pub r#type: RepositoryType,
+    /// Repository URI. Do not include the repository type prefix (ex rest:...)
+    pub uri: String,
+    /// Secret to read the repository password from
+    pub password: SecretKeySelector,
+    /// Rest repository credentials. They are added to the URI, so they must not contain
+    /// characters that need escaping in URLs.
+    pub rest_credentials: Option<RestCredentials>,
+}
+
+impl SecondaryRepository {
+    /// Full URI of the repository, with the credentials taken from the given environment
+    /// variables if it has any.
+    pub fn full_uri(&self, username_env: &str, password_env: &str) -> String {
+        let uri = match &self.rest_credentials {
+            Some(_) => match self.uri.split_once("://") {
+                Some((scheme, rest)) => {
+                    format!("{scheme}://${{{username_env}}}:${{{password_env}}}@{rest}")
+                }
+                None => self.uri.clone(),
+            },
+            None => self.uri.clone(),
+        };
+        match self.r#type {
+            RepositoryType::Rest => format!("rest:{uri}"),
+        }
+    }
+}
+
+/// Named profile backing up some of the paths in its own snapshot series
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupProfile {
+    /// Name of the profile. "default", "manifests" and names starting with "mount-" are reserved.
+    pub name: String,
+    /// Paths to back up, usually mount paths. Mounts backed up by a profile are left out of the
+    /// default profile.
+    pub paths: Vec<String>,
+    /// Snapshot tags added to `restic.backup.tag`
+    pub tag: Option<Vec<String>>,
+    /// Retention policy of the profile's snapshots. Defaults to `restic.retention`.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::retention")]
+    pub retention: Option<Retention>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
 #[serde(rename_all = "camelCase")]
 pub struct Repository {
     /// Repository Type
+    #[schemars(schema_with = "validation::repository_type")]
     pub r#type: RepositoryType,
     /// Repository URI. Do not include the repository type prefix (ex rest:...)
     pub uri: String,
@@ -125,6 +370,12 @@ pub struct Repository {
     pub password: SecretKeySelector,
     /// Rest repository credentials
     pub rest_credentials: Option<RestCredentials>,
+    /// Format version of the repository (1 or 2). Only used to reject options that need version 2.
+    pub version: Option<u32>,
+    /// Maximum number of backup jobs running at once against this repository. Defaults to the
+    /// operator's `RESTIC_OPERATOR_MAX_CONCURRENT_JOBS_PER_REPOSITORY`.
+    #[schemars(range(min = 1))]
+    pub max_concurrent_jobs: Option<u32>,
 }
 
 impl Repository {
@@ -168,6 +419,171 @@ impl Compression {
     }
 }
 
+/// Values are read case-insensitively, and unknown values stored before this was an enum are
+/// read as the default, so existing objects keep deserializing.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq, Default, JsonSchema)]
+pub enum ConcurrencyPolicy {
+    /// Allows backups to run concurrently
+    #[default]
+    Allow,
+    /// Skips the next run if the previous one hasn't finished yet
+    Forbid,
+    /// Cancels the running backup and replaces it with a new one
+    Replace,
+}
+
+impl ConcurrencyPolicy {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConcurrencyPolicy::Allow => "Allow",
+            ConcurrencyPolicy::Forbid => "Forbid",
+            ConcurrencyPolicy::Replace => "Replace",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ConcurrencyPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_lenient(
+            deserializer,
+            &[Self::Allow, Self::Forbid, Self::Replace],
+            Self::as_str,
+        )
+    }
+}
+
+/// Values are read case-insensitively, and unknown values stored before this was an enum are
+/// read as `IfNotPresent`, so existing objects keep deserializing.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq, Default, JsonSchema)]
+pub enum ImagePullPolicy {
+    Always,
+    #[default]
+    IfNotPresent,
+    Never,
+}
+
+impl ImagePullPolicy {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ImagePullPolicy::Always => "Always",
+            ImagePullPolicy::IfNotPresent => "IfNotPresent",
+            ImagePullPolicy::Never => "Never",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ImagePullPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_lenient(
+            deserializer,
+            &[Self::Always, Self::IfNotPresent, Self::Never],
+            Self::as_str,
+        )
+    }
+}
+
+/// Restart policy of a Job's pod. Jobs do not support `Always`.
+///
+/// Values are read case-insensitively, and unknown values stored before this was an enum, like
+/// `Always`, are read as `OnFailure`, so existing objects keep deserializing.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq, Default, JsonSchema)]
+pub enum RestartPolicy {
+    #[default]
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    pub fn as_str(&self) -> &str {
+        match self {
+            RestartPolicy::OnFailure => "OnFailure",
+            RestartPolicy::Never => "Never",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RestartPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_lenient(deserializer, &[Self::OnFailure, Self::Never], Self::as_str)
+    }
+}
+
+/// Deserializes one of `variants` from its name, ignoring case. Unknown names fall back to the
+/// default.
+fn deserialize_lenient<'de, D, T>(
+    deserializer: D,
+    variants: &[T],
+    name: fn(&T) -> &str,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Copy + Default,
+{
+    let value = String::deserialize(deserializer)?;
+    Ok(variants
+        .iter()
+        .find(|v| name(v).eq_ignore_ascii_case(&value))
+        .copied()
+        .unwrap_or_default())
+}
+
+/// Volume holding the restic cache. Exactly one of the sources must be set.
+///
+/// Unless restic runs as root, set `resticProfile.podSecurityContext.fsGroup` so it can write to
+/// persistent caches.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Cache {
+    /// Cache in an emptyDir volume, which only lasts as long as the backup pod. Use `sizeLimit`
+    /// to bound its size.
+    pub empty_dir: Option<EmptyDirVolumeSource>,
+    /// Cache in an existing PersistentVolumeClaim
+    pub persistent_volume_claim: Option<PersistentVolumeClaimVolumeSource>,
+    /// Cache in a PersistentVolumeClaim created by the operator and shared by every backup of the
+    /// same repository in the namespace. The claim is deleted once no backup uses it.
+    pub managed: Option<ManagedCache>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedCache {
+    /// Requested size of the claim, e.g. `5Gi`
+    pub size: Quantity,
+    /// Name of the StorageClass of the claim. Defaults to the cluster's default StorageClass.
+    pub storage_class_name: Option<String>,
+    /// Access modes of the claim. Defaults to `ReadWriteOnce`, which attaches the claim to one
+    /// node at a time, so backups of the same repository on other nodes wait in
+    /// `ContainerCreating` until it is released. Use `ReadWriteMany` or the job queue to avoid
+    /// this.
+    pub access_modes: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, JsonSchema, Builder, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PodMetadata {
+    /// Map of string keys and values that can be used to organize and categorize (scope and select) objects. More info: https://kubernetes.io/docs/concepts/overview/working-with-objects/labels
+    pub labels: Option<BTreeMap<String, String>>,
+    /// Annotations is an unstructured key value map stored with a resource that may be set by external tools to store and retrieve arbitrary metadata. More info: https://kubernetes.io/docs/concepts/overview/working-with-objects/annotations
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+/// Handling of failed backup pods by the exit code of restic.
+///
+/// Restic exits with 1 on a fatal error, 3 when some source files could not be read, 10 when the
+/// repository does not exist, 11 when the repository could not be locked, and 12 when the
+/// password is wrong.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, JsonSchema, Builder, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PodFailurePolicy {
+    /// Exit codes that fail the job immediately without further retries, for example `[3, 10, 12]`
+    pub fail_job_exit_codes: Option<Vec<i32>>,
+    /// Exit codes that are retried without counting towards `backoffLimit`, for example `[11]`
+    pub ignore_exit_codes: Option<Vec<i32>>,
+    /// Retry pods that were disrupted (preempted, evicted or drained) without counting towards
+    /// `backoffLimit`. Defaults to true.
+    pub ignore_disruptions: Option<bool>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, JsonSchema, Builder)]
 #[serde(rename_all = "camelCase")]
 pub struct Retention {
@@ -182,12 +598,39 @@ pub struct Retention {
     pub keep_weekly: Option<u32>,
     pub keep_monthly: Option<u32>,
     pub keep_yearly: Option<u32>,
+    /// Keep all snapshots made within this duration of the latest snapshot, e.g. "2y5m7d3h".
+    pub keep_within: Option<String>,
+    /// Keep the hourly snapshots made within this duration of the latest snapshot.
+    pub keep_within_hourly: Option<String>,
+    /// Keep the daily snapshots made within this duration of the latest snapshot.
+    pub keep_within_daily: Option<String>,
+    /// Keep the weekly snapshots made within this duration of the latest snapshot.
+    pub keep_within_weekly: Option<String>,
+    /// Keep the monthly snapshots made within this duration of the latest snapshot.
+    pub keep_within_monthly: Option<String>,
+    /// Keep the yearly snapshots made within this duration of the latest snapshot.
+    pub keep_within_yearly: Option<String>,
+    /// Keep snapshots with any of these tags.
+    pub keep_tag: Option<Vec<String>>,
+
+    /// Group snapshots by any combination of "host", "paths" and "tags", e.g. "host,tags".
+    /// Defaults to "host,paths".
+    pub group_by: Option<String>,
+    /// Only consider snapshots with any of these tags.
+    pub tag: Option<Vec<String>>,
+    /// Only consider snapshots of these paths.
+    pub path: Option<Vec<String>>,
+    /// Only consider snapshots of the backup's own host, so the retention of a shared repository
+    /// does not forget the snapshots of other backups.
+    #[serde(default)]
+    #[builder(default)]
+    pub own_host: bool,
 
     #[serde(default)]
     pub prune: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, JsonSchema, Builder)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
 #[serde(rename_all = "camelCase")]
 pub struct BackupOptions {
     pub exclude: Option<Vec<String>>,
@@ -196,13 +639,66 @@ pub struct BackupOptions {
     pub exclude_if_present: Option<Vec<String>>,
     pub exclude_larger_than: Option<String>,
     pub iexclude: Option<Vec<String>>,
-    // #[serde(default)]
-    // pub ignore_ctime: bool,
-    // #[serde(default)]
-    // pub ignore_inode: bool,
-    // #[serde(default)]
-    // pub skip_if_unchanged: bool,
+    /// ConfigMap keys holding exclude patterns, one per line.
+    pub exclude_file: Option<Vec<ConfigMapKeySelector>>,
+    /// ConfigMap keys holding case-insensitive exclude patterns, one per line.
+    pub iexclude_file: Option<Vec<ConfigMapKeySelector>>,
+    /// ConfigMap keys holding additional paths to back up, one per line.
+    pub files_from: Option<Vec<ConfigMapKeySelector>>,
+    /// Ignore ctime changes when checking for modified files.
+    #[serde(default)]
+    #[builder(default)]
+    pub ignore_ctime: bool,
+    /// Ignore inode number and ctime changes when checking for modified files.
+    #[serde(default)]
+    #[builder(default)]
+    pub ignore_inode: bool,
+    /// Skip creating a snapshot if it would be identical to the parent snapshot.
+    #[serde(default)]
+    #[builder(default)]
+    pub skip_if_unchanged: bool,
+    /// Exclude other file systems, don't cross file system boundaries and subvolumes.
+    #[serde(default)]
+    #[builder(default)]
+    pub one_file_system: bool,
+    /// Number of files to read concurrently. Defaults to 2.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::positive_u32")]
+    pub read_concurrency: Option<u32>,
+    /// Do not scan the sources to estimate the backup's size and progress.
+    #[serde(default)]
+    #[builder(default)]
+    pub no_scan: bool,
+    /// Store the access time of files and directories.
+    #[serde(default)]
+    #[builder(default)]
+    pub with_atime: bool,
+    /// Exclude cloud files that are only placeholders on Windows. Restic only supports this on
+    /// Windows, so it is rejected as backups run in Linux pods.
+    #[serde(default)]
+    #[builder(default)]
+    pub exclude_cloud_files: bool,
+    /// Do not upload or write any data, just show what would be done. Retention is not applied
+    /// during a dry run.
+    #[serde(default)]
+    #[builder(default)]
+    pub dry_run: bool,
     pub tag: Option<Vec<String>>,
+    /// Fixed host of the snapshots. Overrides `hostFrom`.
+    pub host: Option<String>,
+    /// Where the host of the snapshots comes from, one of:
+    ///
+    /// - "Name" (default): the name of the backup, suffixed with the replica ordinal for StatefulSet backups; - "NamespacedName": the namespace and name of the backup, as `namespace/name`; - "NodeName": the name of the node the backup runs on
+    pub host_from: Option<HostSource>,
+}
+
+/// Source of the host of a backup's snapshots
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Default, JsonSchema)]
+pub enum HostSource {
+    #[default]
+    Name,
+    NamespacedName,
+    NodeName,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder, Default)]
@@ -214,7 +710,7 @@ pub struct ResticProfileConfig {
     /// Will use `latest` if not provided.
     pub version: Option<String>,
     /// Docker Image pull policy
-    pub image_pull_policy: Option<String>,
+    pub image_pull_policy: Option<ImagePullPolicy>,
     /// Arguments to the entrypoint. The container image's CMD is used if this is not provided. Variable references $(VAR_NAME) are expanded using the container's environment. If a variable cannot be resolved, the reference in the input string will be unchanged. Double $$ are reduced to a single $, which allows for escaping the $(VAR_NAME) syntax: i.e. "$$(VAR_NAME)" will produce the string literal "$(VAR_NAME)". Escaped references will never be expanded, regardless of whether the variable exists or not. Cannot be updated. More info: https://kubernetes.io/docs/tasks/inject-data-application/define-command-argument-container/#running-a-command-in-a-shell
     pub args: Option<Vec<String>>,
     /// Entrypoint array. Not executed within a shell. The container image's ENTRYPOINT is used if this is not provided. Variable references $(VAR_NAME) are expanded using the container's environment. If a variable cannot be resolved, the reference in the input string will be unchanged. Double $$ are reduced to a single $, which allows for escaping the $(VAR_NAME) syntax: i.e. "$$(VAR_NAME)" will produce the string literal "$(VAR_NAME)". Escaped references will never be expanded, regardless of whether the variable exists or not. Cannot be updated. More info: https://kubernetes.io/docs/tasks/inject-data-application/define-command-argument-container/#running-a-command-in-a-shell
@@ -225,10 +721,14 @@ pub struct ResticProfileConfig {
     pub env_from: Option<Vec<EnvFromSource>>,
     /// Compute Resources required by this container. More info: https://kubernetes.io/docs/concepts/configuration/manage-resources-containers/
     pub resources: Option<ResourceRequirements>,
-    /// RestartPolicy defines the restart behavior of individual containers in a pod.
-    pub restart_policy: Option<String>,
+    /// Restart policy of the backup pod. Defaults to OnFailure. With Never, failed backups are retried in new pods instead of restarting the container.
+    pub restart_policy: Option<RestartPolicy>,
     /// SecurityContext defines the security options the container should be run with. If set, the fields of SecurityContext override the equivalent fields of PodSecurityContext. More info: https://kubernetes.io/docs/tasks/configure-pod-container/security-context/
     pub security_context: Option<SecurityContext>,
+    /// SecurityContext holds pod-level security attributes and common container settings. Optional: Defaults to empty.  See type description for default values of each field.
+    pub pod_security_context: Option<PodSecurityContext>,
+    /// Run restic as root with the `DAC_READ_SEARCH` capability, so it can read files owned by other users. Otherwise restic runs as a non-root user with a security context compatible with the `restricted` Pod Security Standard. Ignored if `securityContext` is set. Defaults to false.
+    pub needs_root_access: Option<bool>,
 
     /// If specified, the pod's scheduling constraints
     pub affinity: Option<Affinity>,
@@ -236,6 +736,31 @@ pub struct ResticProfileConfig {
     pub node_selector: Option<std::collections::BTreeMap<String, String>>,
     /// ServiceAccountName is the name of the ServiceAccount to use to run this pod. More info: https://kubernetes.io/docs/tasks/configure-pod-container/configure-service-account/
     pub service_account_name: Option<String>,
+    /// If specified, the pod's tolerations.
+    pub tolerations: Option<Vec<Toleration>>,
+    /// If specified, indicates the pod's priority. "system-node-critical" and "system-cluster-critical" are two special keywords which indicate the highest priorities with the former being the highest priority. Any other name must be defined by creating a PriorityClass object with that name. If not specified, the pod priority will be default or zero if there is no default.
+    pub priority_class_name: Option<String>,
+    /// TopologySpreadConstraints describes how a group of pods ought to spread across topology domains. Scheduler will schedule pods in a way which abides by the constraints. All topologySpreadConstraints are ANDed.
+    pub topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    /// RuntimeClassName refers to a RuntimeClass object in the node.k8s.io group, which should be used to run this pod. If no RuntimeClass resource matches the named class, the pod will not be run. If unset or empty, the "legacy" RuntimeClass will be used, which is an implicit class with an empty definition that uses the default runtime handler. More info: https://git.k8s.io/enhancements/keps/sig-node/585-runtime-class
+    pub runtime_class_name: Option<String>,
+    /// Host networking requested for this pod. Use the host's network namespace. If this option is set, the ports that will be used must be specified. Default to false.
+    pub host_network: Option<bool>,
+    /// Specifies the DNS parameters of a pod. Parameters specified here will be merged to the generated DNS configuration based on DNSPolicy.
+    pub dns_config: Option<PodDNSConfig>,
+    /// ImagePullSecrets is an optional list of references to secrets in the same namespace to use for pulling any of the images used by this PodSpec. If specified, these secrets will be passed to individual puller implementations for them to use. More info: https://kubernetes.io/docs/concepts/containers/images#specifying-imagepullsecrets-on-a-pod
+    pub image_pull_secrets: Option<Vec<LocalObjectReference>>,
+    /// Containers run in order before the backup container starts. Sidecars that must keep running alongside restic, such as a tunnel to the repository, should set `restartPolicy: Always` so they do not keep the job from completing.
+    pub init_containers: Option<Vec<Container>>,
+    /// Additional containers run alongside the backup container. The job only completes once all of them have exited. Not allowed with `restic.secondaryRepositories`.
+    pub extra_containers: Option<Vec<Container>>,
+
+    /// Raw resticprofile configuration deep-merged over the generated one, e.g. `{"default": {"backup": {"verbose": true}}}`. Tables are merged key by key, null values remove keys, and any other value replaces the generated one. Applied after `extraConfigFrom`.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::preserve_unknown_fields")]
+    pub extra_config: Option<serde_json::Value>,
+    /// ConfigMap key holding raw resticprofile configuration in TOML, deep-merged over the generated one like `extraConfig`. ScheduledBackups pick up changes to the key on their next reconcile.
+    pub extra_config_from: Option<ConfigMapKeySelector>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
@@ -243,4 +768,104 @@ pub struct ResticProfileConfig {
 pub struct VolumeBackup {
     pub mounts: Vec<VolumeMount>,
     pub volumes: Vec<Volume>,
+    /// Mount the backed up volumes read-only, whatever `readOnly` is set to on the mounts.
+    /// Defaults to true.
+    pub read_only: Option<bool>,
+    /// Extra snapshot tags of each mount, by mount name. Mounts with tags are backed up in their
+    /// own snapshots.
+    pub tags: Option<BTreeMap<String, Vec<String>>>,
+    /// Directory that the files of the mounts are stored relative to in the snapshots, so they
+    /// do not depend on where the volumes are mounted. For example, with `/data` a mount at
+    /// `/data/db` is stored as `db`. Restic still records the absolute paths of the snapshots,
+    /// so changing a mount path starts a new retention group unless `groupBy` leaves out
+    /// "paths", and the next backup does not use the previous snapshot as its parent.
+    pub path_prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSetBackup {
+    /// Name of the StatefulSet in the same namespace
+    pub name: String,
+    /// Name of the volume claim template to backup. Replica `N` of StatefulSet `foo` with
+    /// template `data` uses the PersistentVolumeClaim `data-foo-N`.
+    pub volume_claim_template: String,
+    /// Path to mount each replica's volume at. Defaults to `/data`.
+    pub mount_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct Quiesce {
+    /// Kind of the workload to scale down
+    pub kind: WorkloadKind,
+    /// Name of the workload in the same namespace
+    pub name: String,
+    /// Maximum time in seconds the workload may stay scaled down. Once exceeded, a backup job that
+    /// has not started yet is deleted, a running one is failed, and the workload is scaled back
+    /// up. A Backup whose job is deleted this way is failed. Defaults to 3600.
+    pub timeout_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, JsonSchema)]
+pub enum WorkloadKind {
+    Deployment,
+    StatefulSet,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceBackup {
+    /// Kinds of resources to backup from the namespace
+    pub kinds: Vec<ResourceKind>,
+    /// Label selector the resources must match. All resources of the kinds are backed up if not
+    /// provided.
+    pub selector: Option<LabelSelector>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, JsonSchema, Builder)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceKind {
+    /// API group of the resource. Empty for the core group.
+    #[serde(default)]
+    #[builder(default)]
+    pub group: String,
+    /// API version of the resource
+    pub version: String,
+    /// Kind of the resource
+    pub kind: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_legacy_policies() {
+        let policy = |v| serde_json::from_value::<ConcurrencyPolicy>(json!(v)).unwrap();
+        assert_eq!(policy("Forbid"), ConcurrencyPolicy::Forbid);
+        assert_eq!(policy("replace"), ConcurrencyPolicy::Replace);
+        assert_eq!(policy("sometimes"), ConcurrencyPolicy::Allow);
+
+        let policy = |v| serde_json::from_value::<ImagePullPolicy>(json!(v)).unwrap();
+        assert_eq!(policy("Always"), ImagePullPolicy::Always);
+        assert_eq!(policy("never"), ImagePullPolicy::Never);
+        assert_eq!(policy(""), ImagePullPolicy::IfNotPresent);
+
+        let policy = |v| serde_json::from_value::<RestartPolicy>(json!(v)).unwrap();
+        assert_eq!(policy("Never"), RestartPolicy::Never);
+        assert_eq!(policy("onfailure"), RestartPolicy::OnFailure);
+        assert_eq!(policy("Always"), RestartPolicy::OnFailure);
+
+        // Stored objects with legacy values still deserialize
+        let config: ResticProfileConfig = serde_json::from_value(json!({
+            "imagePullPolicy": "always",
+            "restartPolicy": "Always",
+        }))
+        .unwrap();
+        assert_eq!(config.image_pull_policy, Some(ImagePullPolicy::Always));
+        assert_eq!(config.restart_policy, Some(RestartPolicy::OnFailure));
+    }
 }
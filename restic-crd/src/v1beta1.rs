@@ -0,0 +1,547 @@
+//! The `v1beta1` API version.
+//!
+//! Compared to `v1alpha1`, the repository is moved out of the restic options to the top of the
+//! spec and can be given by reference to a Secret, `resticProfile` is renamed to `pod`, and
+//! `restic.backup` is renamed to `restic.options` so it is no longer confused with
+//! `ScheduledBackup.spec.backup`. Both versions describe the same resources, so converting
+//! between them is lossless.
+
+use std::collections::BTreeMap;
+
+use bon::Builder;
+use k8s_openapi::api::core::v1::{LocalObjectReference, SecretKeySelector};
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    validation, BackupOptions, BackupProfile, BackupStatus, Cache, Compression, ConcurrencyPolicy,
+    PodFailurePolicy, PodMetadata, Quiesce, Repository, RepositoryType, ResourceBackup,
+    ResticProfileConfig, Retention, ScheduledBackupStatus, SecondaryRepository, StatefulSetBackup,
+    VolumeBackup,
+};
+
+/// Annotation on `v1alpha1` objects naming the Secret of a repository given by reference in
+/// `v1beta1`. The operator reads the repository from the Secret instead of the spec.
+pub const REPOSITORY_REF_ANNOTATION: &str = "restic.anshulg.com/repository-ref";
+/// Key of the repository URI in the Secret of a repository given by reference
+pub const REPOSITORY_URI_KEY: &str = "uri";
+/// Key of the repository password in the Secret of a repository given by reference
+pub const REPOSITORY_PASSWORD_KEY: &str = "password";
+/// Keys of the optional REST credentials in the Secret of a repository given by reference
+pub const REST_USERNAME_KEY: &str = "restUsername";
+pub const REST_PASSWORD_KEY: &str = "restPassword";
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema, Builder)]
+#[kube(
+    group = "restic.anshulg.com",
+    version = "v1beta1",
+    kind = "ScheduledBackup",
+    plural = "scheduled-backups",
+    derive = "PartialEq",
+    status = "ScheduledBackupStatus",
+    shortname = "rsb",
+    category = "restic",
+    printcolumn = r#"{"name": "Age", "type": "date", "jsonPath": ".metadata.creationTimestamp"}"#,
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledBackupSpec {
+    /// The schedule in Cron format, see https://en.wikipedia.org/wiki/Cron.
+    ///
+    /// A field can be `H` to pick a value from a hash of the backup's namespace and name, which spreads backups with the same schedule over time. `H(a-b)` limits the value to a range, and `H/n` runs every `n` units from a hashed offset. For example, `H H(1-4) * * *` runs once a day at a fixed time between 1:00 and 4:59.
+    pub schedule: String,
+    /// The backup spec
+    pub backup: BackupSpec,
+    /// Specifies how to treat concurrent executions of a Job. Valid values are:
+    ///
+    /// - "Allow" (default): allows CronJobs to run concurrently; - "Forbid": forbids concurrent runs, skipping next run if previous run hasn't finished yet; - "Replace": cancels currently running job and replaces it with a new one
+    pub concurrency_policy: Option<ConcurrencyPolicy>,
+    /// The number of failed finished jobs to retain. Value must be non-negative integer. Defaults to 1.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub failed_jobs_history_limit: Option<i32>,
+    /// Optional deadline in seconds for starting the job if it misses scheduled time for any reason.  Missed jobs executions will be counted as failed ones.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i64")]
+    pub starting_deadline_seconds: Option<i64>,
+    /// The number of successful finished jobs to retain. Value must be non-negative integer. Defaults to 3.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub successful_jobs_history_limit: Option<i32>,
+    /// This flag tells the controller to suspend subsequent executions, it does not apply to already started executions.  Defaults to false.
+    pub suspend: Option<bool>,
+    /// The time zone name for the given schedule, see https://en.wikipedia.org/wiki/List_of_tz_database_time_zones. If not specified, this will default to the time zone of the kube-controller-manager process.
+    pub time_zone: Option<String>,
+}
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema, Builder)]
+#[kube(
+    group = "restic.anshulg.com",
+    version = "v1beta1",
+    kind = "Backup",
+    plural = "backups",
+    derive = "PartialEq",
+    status = "BackupStatus",
+    shortname = "rb",
+    category = "restic",
+    printcolumn = r#"{"name": "Age", "type": "date", "jsonPath": ".metadata.creationTimestamp"}"#,
+    printcolumn = r#"{"name": "Phase", "type": "string", "jsonPath": ".status.phase"}"#,
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupSpec {
+    /// The Restic Repository Configuration. Exactly one of `repository` and `repositoryRef` must
+    /// be set.
+    pub repository: Option<Repository>,
+
+    /// Secret in the backup's namespace describing a REST repository, so backups of the same
+    /// repository do not repeat it. It holds the repository URI in the `uri` key and its password
+    /// in the `password` key, and may hold REST credentials in the `restUsername` and
+    /// `restPassword` keys. The Secret is read when the backup is reconciled.
+    pub repository_ref: Option<LocalObjectReference>,
+
+    /// Restic Options
+    #[serde(default)]
+    #[builder(default)]
+    pub restic: ResticOptions,
+
+    /// Backup pod Configuration
+    pub pod: Option<ResticProfileConfig>,
+
+    /// Volume Backup
+    pub volume: Option<VolumeBackup>,
+
+    /// StatefulSet Backup. Each replica's volume is backed up in a separate run.
+    pub stateful_set: Option<StatefulSetBackup>,
+
+    /// Workload to scale down while the backup runs
+    pub quiesce: Option<Quiesce>,
+
+    /// Kubernetes resources to backup as YAML manifests, in a separate snapshot tagged
+    /// `manifests`. The manifests are passed to the backup pods in a Secret, so they must not
+    /// exceed 1 MiB. The operator re-collects them at most every 5 minutes, so the manifests of a
+    /// scheduled run can be up to 5 minutes older than the run.
+    pub resources: Option<ResourceBackup>,
+
+    /// Specifies the number of retries before marking the backup job failed. Defaults to 6
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub backoff_limit: Option<i32>,
+
+    /// Specifies the duration in seconds relative to the startTime that the backup job may be
+    /// continuously active before the system tries to terminate it; value must be positive
+    /// integer.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::positive_i64")]
+    pub active_deadline_seconds: Option<i64>,
+
+    /// Limits the lifetime of a backup job that has finished execution (either Complete or
+    /// Failed). If set, the job is eligible to be automatically deleted this many seconds after
+    /// it finishes.
+    #[serde(default)]
+    #[schemars(schema_with = "validation::non_negative_i32")]
+    pub ttl_seconds_after_finished: Option<i32>,
+
+    /// How failed backup pods are handled, by restic exit code. Pods run with
+    /// `restartPolicy: Never` when this is set, as required by Kubernetes.
+    pub pod_failure_policy: Option<PodFailurePolicy>,
+
+    /// Labels and annotations added to the backup pods
+    pub pod_metadata: Option<PodMetadata>,
+
+    /// Labels added to every resource created for the backup, including the pods
+    pub common_labels: Option<BTreeMap<String, String>>,
+
+    /// Annotations added to every resource created for the backup, including the pods
+    pub common_annotations: Option<BTreeMap<String, String>>,
+
+    /// Restic cache kept between backup runs, so the repository index is not downloaded again
+    /// on every run
+    #[serde(default)]
+    #[schemars(schema_with = "validation::cache")]
+    pub cache: Option<Cache>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema, Builder, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ResticOptions {
+    /// Compression mode (only available for repository format version 2), one of (auto/off/max)
+    #[serde(default)]
+    #[builder(default)]
+    pub compression: Compression,
+    /// Set target pack size in MiB, created pack files may be larger
+    pub pack_size: Option<u64>,
+    /// Retention policy
+    #[serde(default)]
+    #[schemars(schema_with = "validation::retention")]
+    pub retention: Option<Retention>,
+    /// Backup Options
+    pub options: Option<BackupOptions>,
+    /// Additional profiles, each backed up in its own snapshots in the same run
+    pub profiles: Option<Vec<BackupProfile>>,
+    /// resticprofile groups by name, each a list of profile names. The job runs every profile by
+    /// default; a group can be run instead with `pod.args`, e.g. `["--name", "media", "backup"]`.
+    pub groups: Option<BTreeMap<String, Vec<String>>>,
+    /// Repositories the backup's snapshots are copied to after each successful backup, e.g. for
+    /// an offsite copy. Each copy runs in its own container once the backup has finished, so
+    /// the backup runs as an init container and `pod.extraContainers` cannot be used. Sidecars
+    /// must be init containers with `restartPolicy: Always` instead.
+    pub secondary_repositories: Option<Vec<SecondaryRepository>>,
+}
+
+impl From<crate::BackupSpec> for BackupSpec {
+    fn from(value: crate::BackupSpec) -> Self {
+        let crate::BackupSpec {
+            restic,
+            restic_profile,
+            volume,
+            stateful_set,
+            quiesce,
+            resources,
+            backoff_limit,
+            active_deadline_seconds,
+            ttl_seconds_after_finished,
+            pod_failure_policy,
+            pod_metadata,
+            common_labels,
+            common_annotations,
+            cache,
+        } = value;
+        let crate::ResticConfig {
+            repository,
+            compression,
+            pack_size,
+            retention,
+            backup,
+            profiles,
+            groups,
+            secondary_repositories,
+        } = restic;
+
+        Self {
+            repository: Some(repository),
+            repository_ref: None,
+            restic: ResticOptions {
+                compression,
+                pack_size,
+                retention,
+                options: backup,
+                profiles,
+                groups,
+                secondary_repositories,
+            },
+            pod: restic_profile,
+            volume,
+            stateful_set,
+            quiesce,
+            resources,
+            backoff_limit,
+            active_deadline_seconds,
+            ttl_seconds_after_finished,
+            pod_failure_policy,
+            pod_metadata,
+            common_labels,
+            common_annotations,
+            cache,
+        }
+    }
+}
+
+impl From<BackupSpec> for crate::BackupSpec {
+    fn from(value: BackupSpec) -> Self {
+        let BackupSpec {
+            repository,
+            repository_ref,
+            restic,
+            pod,
+            volume,
+            stateful_set,
+            quiesce,
+            resources,
+            backoff_limit,
+            active_deadline_seconds,
+            ttl_seconds_after_finished,
+            pod_failure_policy,
+            pod_metadata,
+            common_labels,
+            common_annotations,
+            cache,
+        } = value;
+        let ResticOptions {
+            compression,
+            pack_size,
+            retention,
+            options,
+            profiles,
+            groups,
+            secondary_repositories,
+        } = restic;
+
+        // The Secret is only read by the operator, see [`REPOSITORY_REF_ANNOTATION`]
+        let repository = repository.unwrap_or_else(|| referenced_repository(repository_ref));
+        Self {
+            restic: crate::ResticConfig {
+                repository,
+                compression,
+                pack_size,
+                retention,
+                backup: options,
+                profiles,
+                groups,
+                secondary_repositories,
+            },
+            restic_profile: pod,
+            volume,
+            stateful_set,
+            quiesce,
+            resources,
+            backoff_limit,
+            active_deadline_seconds,
+            ttl_seconds_after_finished,
+            pod_failure_policy,
+            pod_metadata,
+            common_labels,
+            common_annotations,
+            cache,
+        }
+    }
+}
+
+/// Placeholder of a repository given by reference in `v1alpha1`, which names the Secret it is
+/// read from.
+fn referenced_repository(repository_ref: Option<LocalObjectReference>) -> Repository {
+    let name = repository_ref.map(|r| r.name).unwrap_or_default();
+    Repository {
+        r#type: RepositoryType::Rest,
+        uri: String::new(),
+        password: SecretKeySelector {
+            name,
+            key: REPOSITORY_PASSWORD_KEY.to_owned(),
+            optional: None,
+        },
+        rest_credentials: None,
+        version: None,
+        max_concurrent_jobs: None,
+    }
+}
+
+/// Moves the repository reference of a `v1beta1` spec to the annotation of its `v1alpha1` form.
+fn annotate_repository_ref(
+    metadata: &mut kube::api::ObjectMeta,
+    repository_ref: Option<&LocalObjectReference>,
+) {
+    if let Some(r) = repository_ref {
+        metadata
+            .annotations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(REPOSITORY_REF_ANNOTATION.to_owned(), r.name.clone());
+    }
+}
+
+/// Restores the repository reference of a `v1beta1` spec from the annotation of its `v1alpha1`
+/// form.
+fn take_repository_ref(metadata: &mut kube::api::ObjectMeta, spec: &mut BackupSpec) {
+    let name = metadata
+        .annotations
+        .as_mut()
+        .and_then(|a| a.remove(REPOSITORY_REF_ANNOTATION));
+    if metadata.annotations.as_ref().is_some_and(|a| a.is_empty()) {
+        metadata.annotations = None;
+    }
+    if let Some(name) = name {
+        spec.repository = None;
+        spec.repository_ref = Some(LocalObjectReference { name });
+    }
+}
+
+impl From<crate::ScheduledBackupSpec> for ScheduledBackupSpec {
+    fn from(value: crate::ScheduledBackupSpec) -> Self {
+        let crate::ScheduledBackupSpec {
+            schedule,
+            backup,
+            concurrency_policy,
+            failed_jobs_history_limit,
+            starting_deadline_seconds,
+            successful_jobs_history_limit,
+            suspend,
+            time_zone,
+        } = value;
+
+        Self {
+            schedule,
+            backup: backup.into(),
+            concurrency_policy,
+            failed_jobs_history_limit,
+            starting_deadline_seconds,
+            successful_jobs_history_limit,
+            suspend,
+            time_zone,
+        }
+    }
+}
+
+impl From<ScheduledBackupSpec> for crate::ScheduledBackupSpec {
+    fn from(value: ScheduledBackupSpec) -> Self {
+        let ScheduledBackupSpec {
+            schedule,
+            backup,
+            concurrency_policy,
+            failed_jobs_history_limit,
+            starting_deadline_seconds,
+            successful_jobs_history_limit,
+            suspend,
+            time_zone,
+        } = value;
+
+        Self {
+            schedule,
+            backup: backup.into(),
+            concurrency_policy,
+            failed_jobs_history_limit,
+            starting_deadline_seconds,
+            successful_jobs_history_limit,
+            suspend,
+            time_zone,
+        }
+    }
+}
+
+impl From<crate::Backup> for Backup {
+    fn from(value: crate::Backup) -> Self {
+        let mut metadata = value.metadata;
+        let mut spec = value.spec.into();
+        take_repository_ref(&mut metadata, &mut spec);
+        Self {
+            metadata,
+            spec,
+            status: value.status,
+        }
+    }
+}
+
+impl From<Backup> for crate::Backup {
+    fn from(value: Backup) -> Self {
+        let mut metadata = value.metadata;
+        annotate_repository_ref(&mut metadata, value.spec.repository_ref.as_ref());
+        Self {
+            metadata,
+            spec: value.spec.into(),
+            status: value.status,
+        }
+    }
+}
+
+impl From<crate::ScheduledBackup> for ScheduledBackup {
+    fn from(value: crate::ScheduledBackup) -> Self {
+        let mut metadata = value.metadata;
+        let mut spec: ScheduledBackupSpec = value.spec.into();
+        take_repository_ref(&mut metadata, &mut spec.backup);
+        Self {
+            metadata,
+            spec,
+            status: value.status,
+        }
+    }
+}
+
+impl From<ScheduledBackup> for crate::ScheduledBackup {
+    fn from(value: ScheduledBackup) -> Self {
+        let mut metadata = value.metadata;
+        annotate_repository_ref(&mut metadata, value.spec.backup.repository_ref.as_ref());
+        Self {
+            metadata,
+            spec: value.spec.into(),
+            status: value.status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let alpha: crate::ScheduledBackup = serde_json::from_value(json!({
+            "apiVersion": "restic.anshulg.com/v1alpha1",
+            "kind": "ScheduledBackup",
+            "metadata": { "name": "test", "namespace": "default" },
+            "spec": {
+                "schedule": "0 2 * * *",
+                "concurrencyPolicy": "Forbid",
+                "backup": {
+                    "restic": {
+                        "repository": {
+                            "type": "rest",
+                            "uri": "https://example.com",
+                            "password": { "name": "secret", "key": "password" }
+                        },
+                        "compression": "max",
+                        "retention": { "keepDaily": 7, "prune": true },
+                        "backup": { "tag": ["test"] }
+                    },
+                    "resticProfile": { "restartPolicy": "Never" }
+                }
+            }
+        }))
+        .unwrap();
+
+        let beta = ScheduledBackup::from(alpha.clone());
+        let value = serde_json::to_value(&beta).unwrap();
+        assert_eq!(value["apiVersion"], "restic.anshulg.com/v1beta1");
+        assert_eq!(
+            value["spec"]["backup"]["repository"]["uri"],
+            "https://example.com"
+        );
+        assert_eq!(
+            value["spec"]["backup"]["restic"]["options"]["tag"],
+            json!(["test"])
+        );
+        assert_eq!(value["spec"]["backup"]["pod"]["restartPolicy"], "Never");
+
+        assert_eq!(crate::ScheduledBackup::from(beta), alpha);
+    }
+
+    #[test]
+    fn test_repository_ref() {
+        let beta: Backup = serde_json::from_value(json!({
+            "apiVersion": "restic.anshulg.com/v1beta1",
+            "kind": "Backup",
+            "metadata": { "name": "test", "namespace": "default" },
+            "spec": {
+                "repositoryRef": { "name": "offsite" },
+                "restic": { "compression": "max" }
+            }
+        }))
+        .unwrap();
+
+        let alpha = crate::Backup::from(beta.clone());
+        assert_eq!(
+            alpha.metadata.annotations.as_ref().unwrap()[REPOSITORY_REF_ANNOTATION],
+            "offsite"
+        );
+        assert_eq!(alpha.spec.restic.repository.password.name, "offsite");
+        assert_eq!(alpha.spec.restic.compression, Compression::Max);
+
+        let round_trip = Backup::from(alpha);
+        assert_eq!(round_trip, beta);
+        assert_eq!(round_trip.metadata.annotations, None);
+
+        // Other annotations are kept
+        let mut beta = beta;
+        beta.metadata.annotations = Some(BTreeMap::from([("team".to_owned(), "ops".to_owned())]));
+        let alpha = crate::ScheduledBackup::from(ScheduledBackup::new(
+            "test",
+            ScheduledBackupSpec::builder()
+                .schedule("@daily".to_owned())
+                .backup(beta.spec.clone())
+                .build(),
+        ));
+        let round_trip = ScheduledBackup::from(alpha);
+        assert_eq!(round_trip.spec.backup, beta.spec);
+        assert_eq!(Backup::from(crate::Backup::from(beta.clone())), beta);
+    }
+}
@@ -0,0 +1,152 @@
+//! CEL validation rules added to the generated CRD schemas as `x-kubernetes-validations`, so the
+//! API server rejects invalid resources without a webhook, and other schema extensions.
+//!
+//! Each function is used with `#[schemars(schema_with = "...")]` on the field it applies to.
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde_json::json;
+
+/// Generates the schema of `T` with the given `(rule, message)` CEL validation rules.
+fn with_rules<T: JsonSchema>(gen: &mut SchemaGenerator, rules: &[(&str, &str)]) -> Schema {
+    let mut schema = gen.subschema_for::<T>().into_object();
+    let rules: Vec<_> = rules
+        .iter()
+        .map(|(rule, message)| json!({ "rule": rule, "message": message }))
+        .collect();
+    schema
+        .extensions
+        .insert("x-kubernetes-validations".to_owned(), json!(rules));
+    Schema::Object(schema)
+}
+
+pub(crate) fn non_negative_i32(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<i32>>(gen, &[("self >= 0", "must be non-negative")])
+}
+
+pub(crate) fn non_negative_i64(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<i64>>(gen, &[("self >= 0", "must be non-negative")])
+}
+
+pub(crate) fn positive_i64(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<i64>>(gen, &[("self > 0", "must be positive")])
+}
+
+pub(crate) fn positive_u32(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<u32>>(gen, &[("self > 0", "must be positive")])
+}
+
+pub(crate) fn retention(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<crate::Retention>>(
+        gen,
+        &[(
+            "has(self.keepLast) || has(self.keepHourly) || has(self.keepDaily) || has(self.keepWeekly) || has(self.keepMonthly) || has(self.keepYearly) || has(self.keepWithin) || has(self.keepWithinHourly) || has(self.keepWithinDaily) || has(self.keepWithinWeekly) || has(self.keepWithinMonthly) || has(self.keepWithinYearly) || has(self.keepTag)",
+            "retention requires at least one keep policy",
+        )],
+    )
+}
+
+pub(crate) fn cache(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<Option<crate::Cache>>(
+        gen,
+        &[(
+            "[has(self.emptyDir), has(self.persistentVolumeClaim), has(self.managed)].exists_one(x, x)",
+            "cache requires exactly one of emptyDir, persistentVolumeClaim or managed",
+        )],
+    )
+}
+
+/// Name of a secondary repository, which is used in container and volume names of at most 63
+/// characters with the `restic-copy-` prefix.
+pub(crate) fn secondary_repository_name(gen: &mut SchemaGenerator) -> Schema {
+    let Schema::Object(mut schema) = with_rules::<String>(
+        gen,
+        &[(
+            "self.matches('^[a-z0-9]([-a-z0-9]*[a-z0-9])?$')",
+            "must be a DNS label",
+        )],
+    ) else {
+        unreachable!("with_rules returns an object schema");
+    };
+    // Also bounds the cost of the rule
+    schema.string().max_length = Some(51);
+    Schema::Object(schema)
+}
+
+/// Object of any shape, which is only allowed in structural schemas with
+/// `x-kubernetes-preserve-unknown-fields`.
+pub(crate) fn preserve_unknown_fields(_: &mut SchemaGenerator) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    };
+    schema.extensions.insert(
+        "x-kubernetes-preserve-unknown-fields".to_owned(),
+        json!(true),
+    );
+    Schema::Object(schema)
+}
+
+pub(crate) fn repository_type(gen: &mut SchemaGenerator) -> Schema {
+    with_rules::<crate::RepositoryType>(gen, &[("self == oldSelf", "repository type is immutable")])
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::CustomResourceExt;
+
+    use crate::{Backup, ScheduledBackup};
+
+    fn rules(crd: &serde_json::Value, path: &[&str]) -> serde_json::Value {
+        let mut schema = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"];
+        for field in path {
+            schema = &schema["properties"][field];
+        }
+        schema["x-kubernetes-validations"].clone()
+    }
+
+    #[test]
+    fn test_scheduled_backup_rules() {
+        let crd = serde_json::to_value(ScheduledBackup::crd()).unwrap();
+
+        let rule = rules(&crd, &["spec", "failedJobsHistoryLimit"]);
+        assert_eq!(rule[0]["rule"], "self >= 0");
+        let rule = rules(&crd, &["spec", "backup", "restic", "repository", "type"]);
+        assert_eq!(rule[0]["rule"], "self == oldSelf");
+    }
+
+    #[test]
+    fn test_backup_rules() {
+        let crd = serde_json::to_value(Backup::crd()).unwrap();
+
+        let rule = rules(&crd, &["spec", "activeDeadlineSeconds"]);
+        assert_eq!(rule[0]["rule"], "self > 0");
+        let rule = rules(&crd, &["spec", "restic", "backup", "readConcurrency"]);
+        assert_eq!(rule[0]["rule"], "self > 0");
+        let extra = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"]
+            ["properties"]["resticProfile"]["properties"]["extraConfig"];
+        assert_eq!(extra["x-kubernetes-preserve-unknown-fields"], true);
+        let rule = rules(&crd, &["spec", "cache"]);
+        assert!(rule[0]["rule"].as_str().unwrap().contains("exists_one"));
+        let rule = rules(&crd, &["spec", "restic", "retention"]);
+        assert_eq!(
+            rule[0]["message"],
+            "retention requires at least one keep policy"
+        );
+        let name = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"]
+            ["properties"]["restic"]["properties"]["secondaryRepositories"]["items"]["properties"]
+            ["name"];
+        assert_eq!(name["maxLength"], 51);
+        assert_eq!(
+            name["x-kubernetes-validations"][0]["message"],
+            "must be a DNS label"
+        );
+        // Optional fields stay optional
+        let required = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]
+            ["spec"]["properties"]["restic"]["required"];
+        assert_eq!(*required, serde_json::json!(["repository"]));
+    }
+}
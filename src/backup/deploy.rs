@@ -1,24 +1,217 @@
+use k8s_openapi::api::batch::v1::Job;
 use kube::{Client, ResourceExt};
-use restic_crd::Backup;
+use restic_crd::{Backup, BackupPhase, BackupStatus, ReplicaStatus};
 
-use super::job::BackupJob;
+use super::job::{latest_pod, BackupJob};
 use crate::{
+    cache::CacheClaim,
+    config::Config,
     deploy::{Deployable, Labels},
+    jobspec::{aggregate_phase, copy_names, job_phase, CopyPhases},
+    manifests::Manifests,
+    queue,
+    replica::{self, Replica},
     resticprofile::ResticProfile,
     Error,
 };
 
 #[derive(Debug, Clone)]
 pub struct BackupDeployment {
+    runs: Vec<BackupRun>,
+}
+
+#[derive(Debug, Clone)]
+struct BackupRun {
+    replica: Option<Replica>,
     profile: ResticProfile,
+    manifests: Option<Manifests>,
+    cache: Option<CacheClaim>,
     job: BackupJob,
+    /// Names of the secondary repositories the snapshots are copied to
+    copies: Vec<String>,
 }
 
 impl BackupDeployment {
-    pub fn new(ns: String, backup: &Backup) -> Self {
-        let profile = ResticProfile::new(ns.clone(), backup.name_any(), &backup.spec);
-        let job = BackupJob::new(ns, backup, profile.name());
-        Self { profile, job }
+    pub fn new(ns: String, backup: &Backup, replicas: &[Replica], config: &Config) -> Self {
+        let runs = replica::runs(&backup.name_any(), &backup.spec, replicas)
+            .into_iter()
+            .map(|run| {
+                let profile = ResticProfile::new(ns.clone(), run.name.clone(), &run.spec);
+                let manifests = run
+                    .spec
+                    .resources
+                    .clone()
+                    .map(|r| Manifests::new(ns.clone(), &run.name, r));
+                let cache = CacheClaim::new(ns.clone(), &run.spec);
+                let mut job = BackupJob::new(ns.clone(), run.name, &run.spec, profile.name());
+                if config.queue_enabled(&run.spec.restic.repository) {
+                    job = job.with_queue();
+                }
+                let copies = copy_names(&run.spec);
+                BackupRun {
+                    replica: run.replica,
+                    profile,
+                    manifests,
+                    cache,
+                    job,
+                    copies,
+                }
+            })
+            .collect();
+        Self { runs }
+    }
+
+    /// Reads the raw resticprofile config of every run, see [`ResticProfile::resolve`].
+    pub async fn resolve_profiles(&mut self, client: Client) -> Result<(), Error> {
+        for run in &mut self.runs {
+            run.profile.resolve(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the Kubernetes manifests of every run fit in their secret.
+    pub async fn check_manifests(&mut self, client: Client) -> Result<(), Error> {
+        for manifests in self
+            .runs
+            .iter_mut()
+            .filter_map(|run| run.manifests.as_mut())
+        {
+            manifests.check(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Names of the jobs of every run.
+    pub fn job_names(&self) -> Vec<String> {
+        self.runs
+            .iter()
+            .map(|run| run.job.name().to_owned())
+            .collect()
+    }
+
+    /// Gets the jobs of every run that currently exist.
+    pub async fn jobs(&self, client: Client) -> Result<Vec<Job>, Error> {
+        let mut jobs = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            if let Some(job) = run.job.get(client.clone()).await? {
+                jobs.push(job);
+            }
+        }
+        Ok(jobs)
+    }
+
+    /// Gets the status of the deployed sub-resources.
+    ///
+    /// Jobs deleted after finishing, e.g. by `ttlSecondsAfterFinished`, keep the finished phase
+    /// recorded in the previous status.
+    pub async fn status(
+        &self,
+        client: Client,
+        recorded: Option<&BackupStatus>,
+    ) -> Result<BackupStatus, Error> {
+        let mut status = BackupStatus {
+            phase: BackupPhase::Pending,
+            config_map: None,
+            job: None,
+            replicas: None,
+            queue_position: None,
+            copies: None,
+            conditions: recorded.and_then(|s| s.conditions.clone()),
+        };
+        let mut phases = Vec::with_capacity(self.runs.len());
+        let mut copies = CopyPhases::default();
+
+        for run in &self.runs {
+            let job = run.job.get(client.clone()).await?;
+            let phase = match &job {
+                Some(job) => job_phase(job),
+                None => recorded_phase(recorded, run.replica.as_ref()),
+            };
+            phases.push(phase.clone());
+
+            if !run.copies.is_empty() {
+                let pod = match &job {
+                    Some(job) => latest_pod(client.clone(), job).await?,
+                    None => None,
+                };
+                copies.record(
+                    &run.copies,
+                    job.as_ref(),
+                    pod.as_ref(),
+                    recorded.and_then(|s| s.copies.as_deref()),
+                );
+            }
+
+            if let Some(position) = job.as_ref().and_then(queue::queue_position) {
+                status.queue_position = Some(
+                    status
+                        .queue_position
+                        .map_or(position, |p: u32| p.min(position)),
+                );
+            }
+
+            match &run.replica {
+                Some(replica) => {
+                    status
+                        .replicas
+                        .get_or_insert_with(Vec::new)
+                        .push(ReplicaStatus {
+                            ordinal: replica.ordinal,
+                            claim_name: replica.claim_name.clone(),
+                            config_map: Some(run.profile.name().to_owned()),
+                            job: Some(run.job.name().to_owned()),
+                            cron_job: None,
+                            phase,
+                        });
+                }
+                None => {
+                    status.config_map = Some(run.profile.name().to_owned());
+                    status.job = Some(run.job.name().to_owned());
+                }
+            }
+        }
+
+        status.phase = aggregate_phase(&phases);
+        status.copies = copies.statuses();
+        Ok(status)
+    }
+
+    /// Marks the run of the given job and its unfinished copies as failed, e.g. before the job
+    /// is deleted without running.
+    pub fn fail_job(&self, status: &mut BackupStatus, job_name: &str) {
+        let Some(run) = self.runs.iter().find(|run| run.job.name() == job_name) else {
+            return;
+        };
+        match (&run.replica, &mut status.replicas) {
+            (Some(replica), Some(replicas)) => {
+                for r in replicas.iter_mut().filter(|r| r.ordinal == replica.ordinal) {
+                    r.phase = BackupPhase::Failed;
+                }
+                let phases: Vec<_> = replicas.iter().map(|r| r.phase.clone()).collect();
+                status.phase = aggregate_phase(&phases);
+            }
+            _ => status.phase = BackupPhase::Failed,
+        }
+        for copy in status.copies.iter_mut().flatten() {
+            if matches!(copy.phase, BackupPhase::Pending | BackupPhase::Running) {
+                copy.phase = BackupPhase::Failed;
+            }
+        }
+    }
+}
+
+/// Gets the finished phase recorded for a run, or [`BackupPhase::Pending`] if it had not finished.
+fn recorded_phase(recorded: Option<&BackupStatus>, replica: Option<&Replica>) -> BackupPhase {
+    let phase = match replica {
+        Some(replica) => recorded
+            .and_then(|s| s.replicas.as_ref())
+            .and_then(|r| r.iter().find(|r| r.ordinal == replica.ordinal))
+            .map(|r| &r.phase),
+        None => recorded.map(|s| &s.phase),
+    };
+    match phase {
+        Some(phase @ (BackupPhase::Completed | BackupPhase::Failed)) => phase.clone(),
+        _ => BackupPhase::Pending,
     }
 }
 
@@ -29,16 +222,103 @@ impl Deployable for BackupDeployment {
     where
         O: kube::Resource<DynamicType = ()> + Send + Sync,
     {
-        self.profile
-            .create(client.clone(), owner, labels.clone())
-            .await?;
-        self.job.create(client, owner, labels).await?;
+        for run in &self.runs {
+            if let Some(manifests) = &run.manifests {
+                manifests
+                    .create(client.clone(), owner, labels.clone())
+                    .await?;
+            }
+            if let Some(cache) = &run.cache {
+                cache.create(client.clone(), owner, labels.clone()).await?;
+            }
+            run.profile
+                .create(client.clone(), owner, labels.clone())
+                .await?;
+            run.job
+                .create(client.clone(), owner, labels.clone())
+                .await?;
+        }
         Ok(())
     }
 
     async fn delete(&self, client: Client) -> Result<(), Self::Error> {
-        self.profile.delete(client.clone()).await?;
-        self.job.delete(client).await?;
+        for run in &self.runs {
+            if let Some(manifests) = &run.manifests {
+                manifests.delete(client.clone()).await?;
+            }
+            run.profile.delete(client.clone()).await?;
+            run.job.delete(client.clone()).await?;
+            if let Some(cache) = &run.cache {
+                cache.delete(client.clone()).await?;
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use restic_crd::{BackupSpec, CopyStatus, Repository, RepositoryType, ResticConfig};
+
+    use super::*;
+
+    #[test]
+    fn test_recorded_phase() {
+        let status = BackupStatus {
+            phase: BackupPhase::Completed,
+            config_map: None,
+            job: None,
+            replicas: None,
+            queue_position: None,
+            copies: None,
+            conditions: None,
+        };
+        assert_eq!(recorded_phase(Some(&status), None), BackupPhase::Completed);
+        assert_eq!(recorded_phase(None, None), BackupPhase::Pending);
+
+        let status = BackupStatus {
+            phase: BackupPhase::Running,
+            ..status
+        };
+        assert_eq!(recorded_phase(Some(&status), None), BackupPhase::Pending);
+    }
+
+    #[test]
+    fn test_fail_job() {
+        let spec = BackupSpec::builder()
+            .restic(
+                ResticConfig::builder()
+                    .repository(
+                        Repository::builder()
+                            .r#type(RepositoryType::Rest)
+                            .uri("https://example.com".to_owned())
+                            .password(Default::default())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let backup = Backup::new("test", spec);
+        let deployment =
+            BackupDeployment::new("default".to_owned(), &backup, &[], &Config::default());
+        let mut status = BackupStatus {
+            phase: BackupPhase::Pending,
+            config_map: None,
+            job: None,
+            replicas: None,
+            queue_position: None,
+            copies: Some(vec![CopyStatus::builder()
+                .name("offsite".to_owned())
+                .phase(BackupPhase::Pending)
+                .build()]),
+            conditions: None,
+        };
+
+        deployment.fail_job(&mut status, "unknown");
+        assert_eq!(status.phase, BackupPhase::Pending);
+
+        deployment.fail_job(&mut status, &deployment.job_names()[0]);
+        assert_eq!(status.phase, BackupPhase::Failed);
+        assert_eq!(status.copies.unwrap()[0].phase, BackupPhase::Failed);
+    }
+}
@@ -1,8 +1,28 @@
-use k8s_openapi::{api::batch::v1::Job, apimachinery::pkg::apis::meta::v1::OwnerReference};
-use kube::{api::ObjectMeta, Api, Resource, ResourceExt};
-use restic_crd::Backup;
+use k8s_openapi::{
+    api::{batch::v1::Job, core::v1::Pod},
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+};
+use kube::{
+    api::{ListParams, ObjectMeta},
+    Api, Resource, ResourceExt,
+};
+use restic_crd::BackupSpec;
 
-use crate::{deploy::Deployable, jobspec::BackupJobSpec, Error};
+use crate::{deploy::Deployable, jobspec::BackupJobSpec, manifests, Error};
+
+/// Label set by Kubernetes on the pods of a job.
+const JOB_NAME_LABEL: &str = "batch.kubernetes.io/job-name";
+
+/// Gets the most recently created pod of a job, if any.
+pub async fn latest_pod(client: kube::Client, job: &Job) -> Result<Option<Pod>, Error> {
+    let api: Api<Pod> = Api::namespaced(client, &job.namespace().unwrap_or_default());
+    let params = ListParams::default().labels(&format!("{JOB_NAME_LABEL}={}", job.name_any()));
+    let pods = api.list(&params).await?;
+    Ok(pods
+        .items
+        .into_iter()
+        .max_by(|a, b| a.creation_timestamp().cmp(&b.creation_timestamp())))
+}
 
 #[derive(Debug, Clone)]
 pub struct BackupJob {
@@ -12,16 +32,41 @@ pub struct BackupJob {
 }
 
 impl BackupJob {
-    pub fn new(ns: impl Into<String>, backup: &Backup, config_name: impl Into<String>) -> Self {
-        let spec = BackupJobSpec::new(&backup.spec, config_name);
+    pub fn new(
+        ns: impl Into<String>,
+        name: impl Into<String>,
+        backup: &BackupSpec,
+        config_name: impl Into<String>,
+    ) -> Self {
+        let name = name.into();
+        let mut spec = BackupJobSpec::new(backup, config_name);
+        if backup.resources.is_some() {
+            spec = spec.with_manifests(manifests::secret_name(&name));
+        }
         Self {
-            name: format!("{}-job", backup.name_any()),
+            name: format!("{name}-job"),
             ns: ns.into(),
             spec,
         }
     }
 
-    async fn get(&self, client: kube::Client) -> Result<Option<Job>, Error> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs the backup pods on the given node.
+    pub fn with_node_name(mut self, node_name: impl Into<String>) -> Self {
+        self.spec = self.spec.with_node_name(node_name);
+        self
+    }
+
+    /// Holds the job until the queue admits it.
+    pub fn with_queue(mut self) -> Self {
+        self.spec = self.spec.with_queue();
+        self
+    }
+
+    pub async fn get(&self, client: kube::Client) -> Result<Option<Job>, Error> {
         let api: Api<Job> = Api::namespaced(client, &self.ns);
         match api.get(&self.name).await {
             Ok(c) => Ok(Some(c)),
@@ -54,6 +99,12 @@ impl Deployable for BackupJob {
                 name: Some(self.name.clone()),
                 namespace: Some(self.ns.clone()),
                 labels: Some(labels.to_labels()),
+                annotations: Some(
+                    labels
+                        .clone()
+                        .with_annotations(self.spec.annotations().clone())
+                        .to_annotations(),
+                ),
                 owner_references: O::meta(owner).uid.clone().map(|uid| {
                     vec![OwnerReference {
                         api_version: O::api_version(&()).into_owned(),
@@ -66,7 +117,7 @@ impl Deployable for BackupJob {
                 }),
                 ..Default::default()
             },
-            spec: Some(self.spec.clone().into()),
+            spec: Some(self.spec.clone().with_labels(&labels).into()),
             ..Default::default()
         };
 
@@ -2,28 +2,38 @@ use std::{sync::Arc, time::Duration};
 
 use deploy::BackupDeployment;
 use futures::StreamExt;
+use k8s_openapi::api::batch::v1::Job;
 use kube::{
     runtime::{controller::Action, watcher::Config, Controller},
     Api, Client, Resource, ResourceExt,
 };
 use restic_crd::Backup;
-use tracing::{error, info};
+use serde_json::json;
+use tracing::{error, info, warn};
 
 use crate::{
+    config,
     context::ContextData,
     deploy::{Deployable, Labels},
     finalizer::{self, FINALIZER},
-    Error,
+    quiesce,
+    replica::{self, Replica},
+    repository, status, Error,
 };
 
 mod deploy;
-mod job;
+pub mod job;
 
-pub async fn run_controller(client: Client) {
+/// Reason of the `Ready` condition when the job was deleted because the workload was not
+/// quiesced in time.
+const QUIESCE_TIMEOUT: &str = "QuiesceTimeout";
+
+pub async fn run_controller(client: Client, config: config::Config) {
     let crd_api: Api<Backup> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client));
+    let context: Arc<ContextData> = Arc::new(ContextData::new(client, config));
 
     Controller::new(crd_api, Config::default())
+        .owns(Api::<Job>::all(context.client.clone()), Config::default())
         .run(reconcile, on_error, context)
         .for_each(|reconciliation_result| async move {
             match reconciliation_result {
@@ -44,27 +54,152 @@ async fn reconcile(backup: Arc<Backup>, context: Arc<ContextData>) -> Result<Act
     let ns = backup.namespace().ok_or(Error::MissingNamespace)?;
     let name = backup.name_any();
 
+    // Repositories given by reference are read from their Secret, which may be gone on deletion
+    let mut backup = Arc::unwrap_or_clone(backup);
+    if let Err(err) = repository::resolve(client.clone(), &backup.metadata, &mut backup.spec).await
+    {
+        if backup.meta().deletion_timestamp.is_none() {
+            return Err(err);
+        }
+        warn!(name, %err, "Could not read the repository of a deleted backup");
+    }
+
     match determine_action(&backup) {
         BackupAction::Create => {
-            finalizer::add(&Api::<Backup>::namespaced(client.clone(), &ns), &name).await?;
+            let api = Api::<Backup>::namespaced(client.clone(), &ns);
+            let replicas = replica::list(client.clone(), &ns, &backup.spec).await?;
+            let mut deployment = BackupDeployment::new(ns, &backup, &replicas, &context.config);
+
+            // Retried until the manifests fit in their secret, before anything is created
+            deployment.check_manifests(client.clone()).await?;
+
+            // Wait for the raw config to be fixed, before anything is created
+            if let Err(err) = deployment.resolve_profiles(client.clone()).await {
+                if !err.is_invalid_extra_config() {
+                    return Err(err);
+                }
+                warn!(name, %err, "Invalid extra config");
+                let reason = status::INVALID_EXTRA_CONFIG;
+                set_ready(&api, &backup, false, reason, err.to_string()).await?;
+                return Ok(Action::requeue(Duration::from_secs(60)));
+            }
+
+            finalizer::add(&api, &name).await?;
+
+            let labels = Labels::new(name.clone())
+                .with_labels(backup.labels().clone())
+                .with_common(&backup.spec);
+            deployment.create(client.clone(), &backup, labels).await?;
 
-            let deployment = BackupDeployment::new(ns, &backup);
-            let labels = Labels::new(name);
-            deployment.create(client, &*backup, labels).await?;
+            let status = deployment.status(client, None).await?;
+            status::patch(&api, &name, &status).await?;
             Ok(Action::requeue(Duration::from_secs(10)))
         }
         BackupAction::Delete => {
-            let deployment = BackupDeployment::new(ns.clone(), &backup);
+            let deployment = BackupDeployment::new(
+                ns.clone(),
+                &backup,
+                &recorded_replicas(&backup),
+                &context.config,
+            );
             deployment.delete(client.clone()).await?;
 
+            if let Some(q) = &backup.spec.quiesce {
+                quiesce::release(client.clone(), &ns, q, &deployment.job_names()).await?;
+            }
+
             finalizer::remove(&Api::<Backup>::namespaced(client, &ns), &name).await?;
 
             Ok(Action::await_change())
         }
-        BackupAction::Noop => Ok(Action::requeue(Duration::from_secs(10))),
+        BackupAction::Noop => {
+            let deployment = BackupDeployment::new(
+                ns.clone(),
+                &backup,
+                &recorded_replicas(&backup),
+                &context.config,
+            );
+
+            if let Some(q) = &backup.spec.quiesce {
+                let jobs = deployment.jobs(client.clone()).await?;
+                if let Some(job) = quiesce::reconcile(client.clone(), &ns, q, &jobs).await? {
+                    // Recorded first, as the phase of the job is lost once it is deleted
+                    let mut status = deployment
+                        .status(client.clone(), backup.status.as_ref())
+                        .await?;
+                    deployment.fail_job(&mut status, &job.name_any());
+                    let message = format!(
+                        "{} was not quiesced within the timeout, so job {} was deleted before it started",
+                        q.name,
+                        job.name_any()
+                    );
+                    let condition = status::condition(
+                        status::READY,
+                        false,
+                        QUIESCE_TIMEOUT,
+                        message,
+                        backup.metadata.generation,
+                    );
+                    status::set_condition(
+                        status.conditions.get_or_insert_with(Vec::new),
+                        condition,
+                    );
+                    let api = Api::<Backup>::namespaced(client.clone(), &ns);
+                    status::patch(&api, &name, &status).await?;
+
+                    quiesce::abandon(client, &ns, q, &job).await?;
+                    return Ok(Action::requeue(Duration::from_secs(10)));
+                }
+            }
+
+            let status = deployment
+                .status(client.clone(), backup.status.as_ref())
+                .await?;
+            if backup.status.as_ref() != Some(&status) {
+                status::patch(&Api::<Backup>::namespaced(client, &ns), &name, &status).await?;
+            }
+            Ok(Action::requeue(Duration::from_secs(10)))
+        }
     }
 }
 
+/// Sets the `Ready` condition of the backup if it changed.
+async fn set_ready(
+    api: &Api<Backup>,
+    backup: &Backup,
+    ready: bool,
+    reason: &str,
+    message: impl Into<String>,
+) -> Result<(), Error> {
+    let mut conditions = backup
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.clone())
+        .unwrap_or_default();
+    let condition = status::condition(
+        status::READY,
+        ready,
+        reason,
+        message,
+        backup.metadata.generation,
+    );
+    if status::set_condition(&mut conditions, condition) {
+        let patch = json!({ "conditions": conditions });
+        status::patch(api, &backup.name_any(), &patch).await?;
+    }
+    Ok(())
+}
+
+/// Gets the StatefulSet replicas recorded in the status when the backup was created.
+fn recorded_replicas(backup: &Backup) -> Vec<Replica> {
+    backup
+        .status
+        .as_ref()
+        .and_then(|s| s.replicas.as_ref())
+        .map(|r| r.iter().map(Replica::from).collect())
+        .unwrap_or_default()
+}
+
 fn determine_action(backup: &Backup) -> BackupAction {
     if backup.meta().deletion_timestamp.is_some() {
         BackupAction::Delete
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{
+    PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeClaimVolumeSource, Volume,
+    VolumeResourceRequirements,
+};
+use kube::{
+    api::{DeleteParams, ListParams, ObjectMeta},
+    Api, Client, Resource, ResourceExt,
+};
+use restic_crd::{Backup, BackupSpec, ManagedCache, NodeBackup, Repository, ScheduledBackup};
+
+use crate::{
+    deploy::{Deployable, Labels},
+    hash, repository, Error,
+};
+
+/// Directory the cache volume is mounted at in the backup pods.
+pub const CACHE_DIR: &str = "/var/cache/restic";
+/// Name of the cache volume in the backup pods.
+pub const CACHE_VOLUME: &str = "restic-cache";
+
+/// Name of the operator-managed cache claim of a repository.
+pub fn claim_name(repository: &Repository) -> String {
+    format!(
+        "restic-cache-{:08x}",
+        hash::fnv1a(repository.full_uri().as_bytes())
+    )
+}
+
+/// Volume holding the restic cache of a backup, if it has one.
+pub fn volume(backup: &BackupSpec) -> Option<Volume> {
+    let cache = backup.cache.as_ref()?;
+    let persistent_volume_claim = match &cache.managed {
+        Some(_) => Some(PersistentVolumeClaimVolumeSource {
+            claim_name: claim_name(&backup.restic.repository),
+            read_only: None,
+        }),
+        None => cache.persistent_volume_claim.clone(),
+    };
+
+    Some(Volume {
+        name: CACHE_VOLUME.to_owned(),
+        empty_dir: cache.empty_dir.clone(),
+        persistent_volume_claim,
+        ..Default::default()
+    })
+}
+
+/// PersistentVolumeClaim holding the restic cache of a repository, managed by the operator.
+///
+/// The claim is shared by every backup of the repository in the namespace, so it is not owned
+/// by any of them. It is deleted with the last backup that uses it.
+#[derive(Debug, Clone)]
+pub struct CacheClaim {
+    name: String,
+    ns: String,
+    cache: ManagedCache,
+}
+
+impl CacheClaim {
+    pub fn new(ns: String, backup: &BackupSpec) -> Option<Self> {
+        let cache = backup.cache.as_ref()?.managed.clone()?;
+        Some(Self {
+            name: claim_name(&backup.restic.repository),
+            ns,
+            cache,
+        })
+    }
+}
+
+impl Deployable for CacheClaim {
+    type Error = Error;
+
+    async fn create<O>(
+        &self,
+        client: Client,
+        _owner: &O,
+        _labels: Labels,
+    ) -> Result<(), Self::Error>
+    where
+        O: Resource<DynamicType = ()> + Send + Sync,
+    {
+        let claim = PersistentVolumeClaim {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                namespace: Some(self.ns.clone()),
+                labels: Some(Labels::new(self.name.clone()).to_labels()),
+                ..Default::default()
+            },
+            spec: Some(PersistentVolumeClaimSpec {
+                access_modes: Some(
+                    self.cache
+                        .access_modes
+                        .clone()
+                        .unwrap_or_else(|| vec!["ReadWriteOnce".to_owned()]),
+                ),
+                resources: Some(VolumeResourceRequirements {
+                    requests: Some(BTreeMap::from([(
+                        "storage".to_owned(),
+                        self.cache.size.clone(),
+                    )])),
+                    ..Default::default()
+                }),
+                storage_class_name: self.cache.storage_class_name.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // Other backups of the same repository may have created it already
+        let api: Api<PersistentVolumeClaim> = Api::namespaced(client, &self.ns);
+        match api.create(&Default::default(), &claim).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(()),
+            Err(e) => Err(Error::KubeError(e)),
+        }
+    }
+
+    async fn delete(&self, client: Client) -> Result<(), Self::Error> {
+        // Shared with the other backups of the repository, which are not being deleted
+        let backups = Api::<Backup>::namespaced(client.clone(), &self.ns)
+            .list(&ListParams::default())
+            .await?;
+        let scheduled = Api::<ScheduledBackup>::namespaced(client.clone(), &self.ns)
+            .list(&ListParams::default())
+            .await?;
+        let nodes = Api::<NodeBackup>::namespaced(client.clone(), &self.ns)
+            .list(&ListParams::default())
+            .await?;
+        let specs = backups
+            .iter()
+            .map(|b| (b.meta(), &b.spec))
+            .chain(scheduled.iter().map(|b| (b.meta(), &b.spec.backup)))
+            .chain(nodes.iter().map(|b| (b.meta(), &b.spec.backup)))
+            .filter(|(meta, _)| meta.deletion_timestamp.is_none());
+        for (meta, spec) in specs {
+            // The claim is kept if the repository of a backup cannot be read
+            let mut spec = spec.clone();
+            let resolved = repository::resolve(client.clone(), meta, &mut spec).await;
+            if resolved.is_err() || uses_claim(&spec, &self.name) {
+                return Ok(());
+            }
+        }
+
+        // Select by label, so a claim of the same name that the operator did not create is kept
+        let selector = Labels::new(self.name.clone()).selector();
+        let api: Api<PersistentVolumeClaim> = Api::namespaced(client, &self.ns);
+        let claims = api.list(&ListParams::default().labels(&selector)).await?;
+        for claim in claims {
+            match api
+                .delete(&claim.name_any(), &DeleteParams::default())
+                .await
+            {
+                Ok(_) => {}
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the backup uses the operator-managed cache claim of the given name.
+fn uses_claim(backup: &BackupSpec, name: &str) -> bool {
+    backup.cache.as_ref().is_some_and(|c| c.managed.is_some())
+        && claim_name(&backup.restic.repository) == name
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::EmptyDirVolumeSource;
+    use restic_crd::{Cache, RepositoryType, ResticConfig};
+
+    use super::*;
+
+    fn create_backup(cache: Cache) -> BackupSpec {
+        BackupSpec::builder()
+            .restic(
+                ResticConfig::builder()
+                    .repository(
+                        Repository::builder()
+                            .r#type(RepositoryType::Rest)
+                            .uri("https://example.com".to_owned())
+                            .password(Default::default())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .cache(cache)
+            .build()
+    }
+
+    #[test]
+    fn test_volume() {
+        let backup = create_backup(Cache {
+            empty_dir: Some(EmptyDirVolumeSource::default()),
+            ..Default::default()
+        });
+        let volume = volume(&backup).unwrap();
+        assert_eq!(volume.name, CACHE_VOLUME);
+        assert!(volume.empty_dir.is_some());
+        assert!(CacheClaim::new("default".to_owned(), &backup).is_none());
+    }
+
+    #[test]
+    fn test_managed_volume() {
+        let backup = create_backup(Cache {
+            managed: Some(ManagedCache::builder().size(Default::default()).build()),
+            ..Default::default()
+        });
+        let volume = volume(&backup).unwrap();
+        let claim = volume.persistent_volume_claim.unwrap().claim_name;
+        assert_eq!(claim, claim_name(&backup.restic.repository));
+        assert!(claim.starts_with("restic-cache-"));
+        assert!(CacheClaim::new("default".to_owned(), &backup).is_some());
+    }
+
+    #[test]
+    fn test_uses_claim() {
+        let managed = create_backup(Cache {
+            managed: Some(ManagedCache::builder().size(Default::default()).build()),
+            ..Default::default()
+        });
+        let name = claim_name(&managed.restic.repository);
+        assert!(uses_claim(&managed, &name));
+        assert!(!uses_claim(&managed, "restic-cache-00000000"));
+
+        let empty_dir = create_backup(Cache {
+            empty_dir: Some(EmptyDirVolumeSource::default()),
+            ..Default::default()
+        });
+        assert!(!uses_claim(&empty_dir, &name));
+    }
+}
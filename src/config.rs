@@ -0,0 +1,81 @@
+use std::{path::PathBuf, str::FromStr};
+
+use restic_crd::Repository;
+
+use crate::Error;
+
+const MAX_CONCURRENT_JOBS_ENV: &str = "RESTIC_OPERATOR_MAX_CONCURRENT_JOBS";
+const MAX_CONCURRENT_JOBS_PER_REPOSITORY_ENV: &str =
+    "RESTIC_OPERATOR_MAX_CONCURRENT_JOBS_PER_REPOSITORY";
+const WEBHOOK_PORT_ENV: &str = "RESTIC_OPERATOR_WEBHOOK_PORT";
+const WEBHOOK_CERT_DIR_ENV: &str = "RESTIC_OPERATOR_WEBHOOK_CERT_DIR";
+const WEBHOOK_SERVICE_ENV: &str = "RESTIC_OPERATOR_WEBHOOK_SERVICE";
+const WEBHOOK_CONFIGURATION_ENV: &str = "RESTIC_OPERATOR_WEBHOOK_CONFIGURATION";
+
+const DEFAULT_WEBHOOK_CERT_DIR: &str = "/etc/restic-operator/tls";
+
+/// Operator-wide settings, read from environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Maximum number of backup jobs running at once in the cluster.
+    pub max_concurrent_jobs: Option<usize>,
+    /// Maximum number of backup jobs running at once against a single repository.
+    pub max_concurrent_jobs_per_repository: Option<usize>,
+    /// Port of the validating admission webhook server. The server is disabled if unset.
+    pub webhook_port: Option<u16>,
+    /// Directory holding the webhook's `tls.crt` and `tls.key`, such as a mounted cert-manager
+    /// secret.
+    pub webhook_cert_dir: Option<PathBuf>,
+    /// DNS name of the webhook service, e.g. `restic-operator.restic-system.svc`. Used to
+    /// generate a self-signed certificate when none is found in the certificate directory.
+    pub webhook_service: Option<String>,
+    /// Name of the `ValidatingWebhookConfiguration` to inject the self-signed CA bundle into.
+    pub webhook_configuration: Option<String>,
+}
+
+impl Config {
+    /// Reads the settings from the environment.
+    ///
+    /// - `RESTIC_OPERATOR_MAX_CONCURRENT_JOBS`: see [`Config::max_concurrent_jobs`].
+    /// - `RESTIC_OPERATOR_MAX_CONCURRENT_JOBS_PER_REPOSITORY`: see
+    ///   [`Config::max_concurrent_jobs_per_repository`].
+    /// - `RESTIC_OPERATOR_WEBHOOK_PORT`: see [`Config::webhook_port`].
+    /// - `RESTIC_OPERATOR_WEBHOOK_CERT_DIR`: see [`Config::webhook_cert_dir`].
+    /// - `RESTIC_OPERATOR_WEBHOOK_SERVICE`: see [`Config::webhook_service`].
+    /// - `RESTIC_OPERATOR_WEBHOOK_CONFIGURATION`: see [`Config::webhook_configuration`].
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(Self {
+            max_concurrent_jobs: parse_env(MAX_CONCURRENT_JOBS_ENV)?,
+            max_concurrent_jobs_per_repository: parse_env(MAX_CONCURRENT_JOBS_PER_REPOSITORY_ENV)?,
+            webhook_port: parse_env(WEBHOOK_PORT_ENV)?,
+            webhook_cert_dir: parse_env(WEBHOOK_CERT_DIR_ENV)?,
+            webhook_service: parse_env(WEBHOOK_SERVICE_ENV)?,
+            webhook_configuration: parse_env(WEBHOOK_CONFIGURATION_ENV)?,
+        })
+    }
+
+    /// Whether backup jobs against the repository have to wait for a slot in the queue before
+    /// starting.
+    pub const fn queue_enabled(&self, repository: &Repository) -> bool {
+        self.max_concurrent_jobs.is_some()
+            || self.max_concurrent_jobs_per_repository.is_some()
+            || repository.max_concurrent_jobs.is_some()
+    }
+
+    /// Directory holding the webhook's certificate, defaulting to `/etc/restic-operator/tls`.
+    pub fn webhook_cert_dir(&self) -> PathBuf {
+        self.webhook_cert_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_WEBHOOK_CERT_DIR))
+    }
+}
+
+fn parse_env<T: FromStr>(name: &'static str) -> Result<Option<T>, Error> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidConfig { name, value }),
+        _ => Ok(None),
+    }
+}
@@ -1,9 +1,13 @@
 use kube::Client;
 
+use crate::config::Config;
+
 /// Context injected with each `reconcile` and `on_error` method invocation.
 pub struct ContextData {
     /// Kubernetes client to make Kubernetes API requests with.
     pub client: Client,
+    /// Operator settings.
+    pub config: Config,
 }
 
 impl ContextData {
@@ -12,7 +16,8 @@ impl ContextData {
     /// # Arguments:
     /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
     ///   will be created and deleted with this client.
-    pub fn new(client: Client) -> Self {
-        ContextData { client }
+    /// - `config`: Operator settings.
+    pub fn new(client: Client, config: Config) -> Self {
+        ContextData { client, config }
     }
 }
@@ -1,6 +1,7 @@
 use std::{collections::BTreeMap, future::Future};
 
 use kube::{Client, Resource};
+use restic_crd::BackupSpec;
 
 pub trait Deployable {
     type Error;
@@ -16,20 +17,47 @@ pub trait Deployable {
     fn delete(&self, client: Client) -> impl Future<Output = Result<(), Self::Error>> + Send;
 }
 
+/// Labels and annotations of the resources created for a backup.
 #[derive(Debug, Clone)]
 pub struct Labels {
     app_name: String,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
 }
 
 impl Labels {
     pub fn new(app_name: impl Into<String>) -> Self {
         Self {
             app_name: app_name.into(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
         }
     }
 
+    /// Adds extra labels, overriding earlier ones with the same key.
+    pub fn with_labels(mut self, labels: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.labels.extend(labels);
+        self
+    }
+
+    /// Adds extra annotations, overriding earlier ones with the same key.
+    pub fn with_annotations(
+        mut self,
+        annotations: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        self.annotations.extend(annotations);
+        self
+    }
+
+    /// Adds the `commonLabels` and `commonAnnotations` of a backup spec.
+    pub fn with_common(self, spec: &BackupSpec) -> Self {
+        self.with_labels(spec.common_labels.clone().unwrap_or_default())
+            .with_annotations(spec.common_annotations.clone().unwrap_or_default())
+    }
+
     pub fn to_labels(&self) -> BTreeMap<String, String> {
-        let mut labels = BTreeMap::new();
+        // The operator's labels always win, as they are used to find its resources
+        let mut labels = self.labels.clone();
         labels.insert(
             "app.kubernetes.io/name".to_owned(),
             self.app_name.to_owned(),
@@ -40,4 +68,44 @@ impl Labels {
         );
         labels
     }
+
+    /// Label selector matching the operator's labels, without the extra ones.
+    pub fn selector(&self) -> String {
+        Labels::new(self.app_name.clone())
+            .to_labels()
+            .into_iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn to_annotations(&self) -> BTreeMap<String, String> {
+        self.annotations.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        let labels = Labels::new("test")
+            .with_labels([
+                ("team".to_owned(), "storage".to_owned()),
+                ("app.kubernetes.io/name".to_owned(), "other".to_owned()),
+            ])
+            .with_labels([("team".to_owned(), "backup".to_owned())])
+            .with_annotations([("owner".to_owned(), "me".to_owned())]);
+
+        let map = labels.to_labels();
+        assert_eq!(map["team"], "backup");
+        assert_eq!(map["app.kubernetes.io/name"], "test");
+        assert_eq!(map["app.kubernetes.io/managed-by"], "restic-operator");
+        assert_eq!(labels.to_annotations()["owner"], "me");
+        assert_eq!(
+            labels.selector(),
+            "app.kubernetes.io/managed-by=restic-operator,app.kubernetes.io/name=test"
+        );
+    }
 }
@@ -6,7 +6,69 @@ pub enum Error {
     /// Error in serializing the resticprofile config to TOML
     #[error("Error creating resticprofile config: {0}")]
     TomlSerializeError(#[from] toml::ser::Error),
+    /// Error in parsing raw resticprofile config from TOML
+    #[error("Invalid resticprofile config: {0}")]
+    TomlDeserializeError(#[from] toml::de::Error),
+    /// A referenced ConfigMap key does not exist
+    #[error("Key {key:?} not found in ConfigMap {name:?}")]
+    MissingConfigMapKey { name: String, key: String },
+    /// A referenced Secret key does not exist
+    #[error("Key {key:?} not found in Secret {name:?}")]
+    MissingSecretKey { name: String, key: String },
+    /// Error in serializing Kubernetes manifests to YAML
+    #[error("Error serializing manifests: {0}")]
+    YamlSerializeError(#[from] serde_yaml::Error),
+    /// Invalid label selector
+    #[error("Invalid label selector: {0}")]
+    InvalidSelector(#[from] kube::core::ParseExpressionError),
+    /// Invalid operator setting
+    #[error("Invalid value for {name}: {value:?}")]
+    InvalidConfig { name: &'static str, value: String },
+    /// Invalid cron schedule
+    #[error("Invalid schedule {schedule:?}: {source}")]
+    InvalidSchedule {
+        schedule: String,
+        source: croner::errors::CronError,
+    },
+    /// Cron token accepted by croner but not by Kubernetes
+    #[error("Invalid schedule {schedule:?}: {token:?} is not supported by Kubernetes")]
+    UnsupportedScheduleToken { schedule: String, token: char },
+    /// Kubernetes manifests too large to be stored in a Secret
+    #[error("Manifests in {name:?} are {size} bytes, more than a Secret can hold (1 MiB); narrow down resources.kinds or resources.selector")]
+    ManifestsTooLarge { name: String, size: usize },
+    /// Unknown IANA time zone
+    #[error("Unknown time zone {0:?}")]
+    InvalidTimeZone(String),
+    /// I/O error, such as in the webhook server
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// Invalid TLS configuration of the webhook server
+    #[error("TLS error: {0}")]
+    TlsError(#[from] tokio_rustls::rustls::Error),
+    /// Error generating the self-signed webhook certificate
+    #[error("Error generating certificate: {0}")]
+    CertificateError(#[from] rcgen::Error),
+    /// No webhook certificate was found and none could be generated
+    #[error("Webhook certificate not found: {0:?}")]
+    MissingWebhookCertificate(std::path::PathBuf),
+    /// Error in (de)serializing a resource as JSON
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    /// Conversion to an unknown API version or kind
+    #[error("Cannot convert {kind} to {version}")]
+    UnsupportedConversion { kind: String, version: String },
     /// Missing Namespace
     #[error("Namespace not found")]
     MissingNamespace,
 }
+
+impl Error {
+    /// Whether the error comes from the raw resticprofile config in `extraConfigFrom`, which only
+    /// the user can fix.
+    pub fn is_invalid_extra_config(&self) -> bool {
+        matches!(
+            self,
+            Error::MissingConfigMapKey { .. } | Error::TomlDeserializeError(_)
+        )
+    }
+}
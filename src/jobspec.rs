@@ -1,30 +1,74 @@
+use std::collections::BTreeMap;
+
 use k8s_openapi::api::{
-    batch::v1::JobSpec,
+    batch::v1::{
+        Job, JobSpec, PodFailurePolicy as JobFailurePolicy, PodFailurePolicyOnExitCodesRequirement,
+        PodFailurePolicyOnPodConditionsPattern, PodFailurePolicyRule,
+    },
     core::v1::{
-        Affinity, ConfigMapVolumeSource, Container, EnvFromSource, EnvVar, EnvVarSource, PodSpec,
-        PodTemplateSpec, ResourceRequirements, SecretVolumeSource, SecurityContext, Volume,
-        VolumeMount,
+        Affinity, Capabilities, ConfigMapProjection, ConfigMapVolumeSource, Container,
+        ContainerState, EnvFromSource, EnvVar, EnvVarSource, KeyToPath, LocalObjectReference,
+        ObjectFieldSelector, Pod, PodDNSConfig, PodSecurityContext, PodSpec, PodTemplateSpec,
+        ProjectedVolumeSource, ResourceRequirements, SeccompProfile, SecretVolumeSource,
+        SecurityContext, Toleration, TopologySpreadConstraint, Volume, VolumeMount,
+        VolumeProjection,
     },
 };
-use restic_crd::{BackupSpec, ResticProfileConfig};
+use kube::api::ObjectMeta;
+use restic_crd::{
+    BackupPhase, BackupSpec, CopyStatus, HostSource, ImagePullPolicy, PodFailurePolicy,
+    RestartPolicy, ResticProfileConfig, SecondaryRepository,
+};
+
+use crate::{
+    cache,
+    deploy::Labels,
+    queue, quiesce,
+    resticprofile::{self, config::DEFAULT_GROUP},
+};
 
 const DEFAULT_RESTIC_IMAGE: &str = "creativeprojects/resticprofile";
+/// User and group restic runs as when it does not need root access.
+const NON_ROOT_USER: i64 = 65532;
+/// Name of the container running resticprofile in the backup pods.
+pub const CONTAINER_NAME: &str = "restic-backup";
 
 #[derive(Debug, Clone)]
 pub struct BackupJobSpec {
     image: String,
-    image_pull_policy: Option<String>,
+    image_pull_policy: Option<ImagePullPolicy>,
+    restart_policy: RestartPolicy,
     args: Option<Vec<String>>,
     command: Option<Vec<String>>,
     env: Vec<EnvVar>,
     env_from: Vec<EnvFromSource>,
     resources: Option<ResourceRequirements>,
     security_context: Option<SecurityContext>,
+    pod_security_context: Option<PodSecurityContext>,
     affinity: Option<Affinity>,
     node_selector: Option<std::collections::BTreeMap<String, String>>,
+    node_name: Option<String>,
     service_account_name: Option<String>,
+    tolerations: Option<Vec<Toleration>>,
+    priority_class_name: Option<String>,
+    topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    runtime_class_name: Option<String>,
+    host_network: Option<bool>,
+    dns_config: Option<PodDNSConfig>,
+    image_pull_secrets: Option<Vec<LocalObjectReference>>,
+    init_containers: Option<Vec<Container>>,
+    extra_containers: Vec<Container>,
     volume_mounts: Vec<VolumeMount>,
     volumes: Vec<Volume>,
+    backoff_limit: Option<i32>,
+    active_deadline_seconds: Option<i64>,
+    ttl_seconds_after_finished: Option<i32>,
+    pod_failure_policy: Option<JobFailurePolicy>,
+    suspend: bool,
+    annotations: BTreeMap<String, String>,
+    pod_labels: BTreeMap<String, String>,
+    pod_annotations: BTreeMap<String, String>,
+    secondary_repositories: Vec<SecondaryRepository>,
 }
 
 impl BackupJobSpec {
@@ -36,60 +80,468 @@ impl BackupJobSpec {
 
         // If no args or command is provided, default args to "backup"
         if rpcfg.args.is_none() && rpcfg.command.is_none() {
-            rpcfg.args = Some(vec!["backup".to_owned()]);
+            rpcfg.args = Some(if resticprofile::uses_group(backup) {
+                // Run the manifests and mount profiles alongside the default one
+                vec![
+                    "--name".to_owned(),
+                    DEFAULT_GROUP.to_owned(),
+                    "backup".to_owned(),
+                ]
+            } else {
+                vec!["backup".to_owned()]
+            });
+        }
+
+        let mut annotations = BTreeMap::from([(
+            queue::REPOSITORY_ANNOTATION.to_owned(),
+            backup.restic.repository.full_uri(),
+        )]);
+        if let Some(max) = backup.restic.repository.max_concurrent_jobs {
+            annotations.insert(
+                queue::MAX_CONCURRENT_JOBS_ANNOTATION.to_owned(),
+                max.to_string(),
+            );
+        }
+        if let Some(q) = &backup.quiesce {
+            annotations.insert(quiesce::QUIESCE_ANNOTATION.to_owned(), q.name.clone());
         }
+        let pod_metadata = backup.pod_metadata.clone().unwrap_or_default();
 
         Self {
             image,
             image_pull_policy: rpcfg.image_pull_policy.take(),
+            // Pod failure policies require pods that are not restarted in place
+            restart_policy: rpcfg.restart_policy.take().unwrap_or(
+                match backup.pod_failure_policy {
+                    Some(_) => RestartPolicy::Never,
+                    None => RestartPolicy::default(),
+                },
+            ),
             args: rpcfg.args.take(),
             command: rpcfg.command.take(),
             env,
             env_from: rpcfg.env_from.take().unwrap_or_default(),
             resources: rpcfg.resources.take(),
-            security_context: rpcfg.security_context.take(),
+            security_context: rpcfg
+                .security_context
+                .take()
+                .or_else(|| Some(default_security_context(&rpcfg))),
+            pod_security_context: rpcfg.pod_security_context.take(),
             affinity: rpcfg.affinity.take(),
             node_selector: rpcfg.node_selector.take(),
+            node_name: None,
             service_account_name: rpcfg.service_account_name.take(),
+            tolerations: rpcfg.tolerations.take(),
+            priority_class_name: rpcfg.priority_class_name.take(),
+            topology_spread_constraints: rpcfg.topology_spread_constraints.take(),
+            runtime_class_name: rpcfg.runtime_class_name.take(),
+            host_network: rpcfg.host_network.take(),
+            dns_config: rpcfg.dns_config.take(),
+            image_pull_secrets: rpcfg.image_pull_secrets.take(),
+            init_containers: rpcfg.init_containers.take(),
+            extra_containers: rpcfg.extra_containers.take().unwrap_or_default(),
             volume_mounts,
             volumes,
+            backoff_limit: backup.backoff_limit,
+            active_deadline_seconds: backup.active_deadline_seconds,
+            ttl_seconds_after_finished: backup.ttl_seconds_after_finished,
+            pod_failure_policy: backup.pod_failure_policy.as_ref().map(pod_failure_policy),
+            // Quiesced backups are started once the workload has been scaled down
+            suspend: backup.quiesce.is_some(),
+            annotations,
+            pod_labels: pod_metadata.labels.unwrap_or_default(),
+            pod_annotations: pod_metadata.annotations.unwrap_or_default(),
+            secondary_repositories: backup
+                .restic
+                .secondary_repositories
+                .clone()
+                .unwrap_or_default(),
         }
     }
+
+    /// Holds the job suspended until the queue admits it.
+    pub fn with_queue(mut self) -> Self {
+        self.suspend = true;
+        self.annotations
+            .insert(queue::QUEUED_ANNOTATION.to_owned(), "true".to_owned());
+        self
+    }
+
+    /// Runs the pods on the given node, bypassing the scheduler.
+    pub fn with_node_name(mut self, node_name: impl Into<String>) -> Self {
+        self.node_name = Some(node_name.into());
+        self
+    }
+
+    /// Adds the labels and annotations of the backup's resources to the pods, under the pod's
+    /// own.
+    pub fn with_labels(mut self, labels: &Labels) -> Self {
+        let pod = labels
+            .clone()
+            .with_labels(std::mem::take(&mut self.pod_labels))
+            .with_annotations(std::mem::take(&mut self.pod_annotations));
+        self.pod_labels = pod.to_labels();
+        self.pod_annotations = pod.to_annotations();
+        self
+    }
+
+    /// Annotations to set on the job.
+    pub fn annotations(&self) -> &BTreeMap<String, String> {
+        &self.annotations
+    }
 }
 
 impl From<BackupJobSpec> for JobSpec {
     fn from(value: BackupJobSpec) -> Self {
+        let backup = Container {
+            name: CONTAINER_NAME.to_owned(),
+            args: value.args,
+            command: value.command,
+            env: Some(value.env),
+            env_from: Some(value.env_from),
+            image: Some(value.image),
+            image_pull_policy: value.image_pull_policy.map(|p| p.as_str().to_owned()),
+            resources: value.resources,
+            security_context: value.security_context,
+            volume_mounts: Some(value.volume_mounts),
+            ..Default::default()
+        };
+
+        // Copies start once the backup has finished, so it runs as the last init container
+        let (init_containers, containers) = if value.secondary_repositories.is_empty() {
+            (value.init_containers, vec![backup])
+        } else {
+            let copies = value
+                .secondary_repositories
+                .iter()
+                .map(|repo| copy_container(&backup, repo))
+                .collect();
+            let mut init_containers = value.init_containers.unwrap_or_default();
+            init_containers.push(backup);
+            (Some(init_containers), copies)
+        };
+
         Self {
-            suspend: Some(false),
+            suspend: Some(value.suspend),
+            backoff_limit: value.backoff_limit,
+            active_deadline_seconds: value.active_deadline_seconds,
+            ttl_seconds_after_finished: value.ttl_seconds_after_finished,
+            pod_failure_policy: value.pod_failure_policy,
             template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(value.pod_labels).filter(|l| !l.is_empty()),
+                    annotations: Some(value.pod_annotations).filter(|a| !a.is_empty()),
+                    ..Default::default()
+                }),
                 spec: Some(PodSpec {
                     affinity: value.affinity,
-                    containers: vec![Container {
-                        name: "restic-backup".to_owned(),
-                        args: value.args,
-                        command: value.command,
-                        env: Some(value.env),
-                        env_from: Some(value.env_from),
-                        image: Some(value.image),
-                        image_pull_policy: value.image_pull_policy,
-                        resources: value.resources,
-                        security_context: value.security_context,
-                        volume_mounts: Some(value.volume_mounts),
-                        ..Default::default()
-                    }],
-                    restart_policy: Some("OnFailure".to_string()),
+                    init_containers,
+                    containers: containers
+                        .into_iter()
+                        .chain(value.extra_containers)
+                        .collect(),
+                    restart_policy: Some(value.restart_policy.as_str().to_owned()),
+                    security_context: value.pod_security_context,
                     node_selector: value.node_selector,
+                    node_name: value.node_name,
                     service_account_name: value.service_account_name,
+                    tolerations: value.tolerations,
+                    priority_class_name: value.priority_class_name,
+                    topology_spread_constraints: value.topology_spread_constraints,
+                    runtime_class_name: value.runtime_class_name,
+                    host_network: value.host_network,
+                    dns_config: value.dns_config,
+                    image_pull_secrets: value.image_pull_secrets,
                     volumes: Some(value.volumes),
                     ..Default::default()
                 }),
-                ..Default::default()
             },
             ..Default::default()
         }
     }
 }
 
+/// Name of the container copying the snapshots to a secondary repository, and of the volume
+/// holding the repository's password. Secondary repository names are limited to 51 characters,
+/// so it is a valid container and volume name.
+pub fn copy_container_name(repository: &str) -> String {
+    format!("restic-copy-{repository}")
+}
+
+/// Container copying the snapshots to a secondary repository, run like the backup container.
+fn copy_container(backup: &Container, repo: &SecondaryRepository) -> Container {
+    let mut env = backup.env.clone().unwrap_or_default();
+    if let Some(rest_creds) = &repo.rest_credentials {
+        let creds = [
+            (resticprofile::COPY_USERNAME_ENV, &rest_creds.username),
+            (resticprofile::COPY_PASSWORD_ENV, &rest_creds.password),
+        ];
+        env.extend(creds.into_iter().map(|(name, secret)| EnvVar {
+            name: name.to_owned(),
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(secret.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+    }
+
+    Container {
+        name: copy_container_name(&repo.name),
+        args: Some(vec![
+            "--name".to_owned(),
+            resticprofile::copy_profile(&repo.name),
+            "copy".to_owned(),
+        ]),
+        command: None,
+        env: Some(env),
+        ..backup.clone()
+    }
+}
+
+/// Translates the restic exit codes of a [`PodFailurePolicy`] into the rules of a job.
+fn pod_failure_policy(policy: &PodFailurePolicy) -> JobFailurePolicy {
+    let mut rules = Vec::new();
+
+    if policy.ignore_disruptions.unwrap_or(true) {
+        rules.push(PodFailurePolicyRule {
+            action: "Ignore".to_owned(),
+            on_pod_conditions: Some(vec![PodFailurePolicyOnPodConditionsPattern {
+                type_: "DisruptionTarget".to_owned(),
+                status: "True".to_owned(),
+            }]),
+            on_exit_codes: None,
+        });
+    }
+
+    let actions = [
+        ("FailJob", &policy.fail_job_exit_codes),
+        ("Ignore", &policy.ignore_exit_codes),
+    ];
+    for (action, codes) in actions {
+        // The API server requires the values to be sorted, unique and non-zero
+        let mut values: Vec<i32> = codes
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|c| *c != 0)
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+        if values.is_empty() {
+            continue;
+        }
+
+        rules.push(PodFailurePolicyRule {
+            action: action.to_owned(),
+            on_exit_codes: Some(PodFailurePolicyOnExitCodesRequirement {
+                container_name: Some(CONTAINER_NAME.to_owned()),
+                operator: "In".to_owned(),
+                values,
+            }),
+            on_pod_conditions: None,
+        });
+    }
+
+    JobFailurePolicy { rules }
+}
+
+/// Determines the [`BackupPhase`] of a job from its status.
+pub fn job_phase(job: &Job) -> BackupPhase {
+    let Some(status) = &job.status else {
+        return BackupPhase::Pending;
+    };
+
+    let failed = status
+        .conditions
+        .as_ref()
+        .is_some_and(|c| c.iter().any(|c| c.type_ == "Failed" && c.status == "True"));
+
+    if status.succeeded.unwrap_or_default() > 0 {
+        BackupPhase::Completed
+    } else if failed {
+        BackupPhase::Failed
+    } else if status.active.unwrap_or_default() > 0 {
+        BackupPhase::Running
+    } else {
+        BackupPhase::Pending
+    }
+}
+
+/// Determines the [`BackupPhase`] of a container of a backup pod from its state.
+pub fn container_phase(pod: &Pod, container: &str) -> BackupPhase {
+    let state = pod
+        .status
+        .as_ref()
+        .and_then(|s| s.container_statuses.as_ref())
+        .and_then(|c| c.iter().find(|c| c.name == container))
+        .and_then(|c| c.state.as_ref());
+    match state {
+        Some(ContainerState {
+            terminated: Some(terminated),
+            ..
+        }) if terminated.exit_code == 0 => BackupPhase::Completed,
+        Some(ContainerState {
+            terminated: Some(_),
+            ..
+        }) => BackupPhase::Failed,
+        Some(ContainerState {
+            running: Some(_), ..
+        }) => BackupPhase::Running,
+        _ => BackupPhase::Pending,
+    }
+}
+
+/// Names of the secondary repositories the snapshots of a backup are copied to.
+pub fn copy_names(spec: &BackupSpec) -> Vec<String> {
+    spec.restic
+        .secondary_repositories
+        .iter()
+        .flatten()
+        .map(|r| r.name.clone())
+        .collect()
+}
+
+/// Combines the phases of every run into the phase of the whole backup.
+///
+/// A backup without runs, e.g. of a StatefulSet scaled to zero, is pending.
+pub fn aggregate_phase(phases: &[BackupPhase]) -> BackupPhase {
+    if phases.is_empty() {
+        BackupPhase::Pending
+    } else if phases.contains(&BackupPhase::Failed) {
+        BackupPhase::Failed
+    } else if phases.iter().all(|p| *p == BackupPhase::Completed) {
+        BackupPhase::Completed
+    } else if phases.contains(&BackupPhase::Running) {
+        BackupPhase::Running
+    } else {
+        BackupPhase::Pending
+    }
+}
+
+/// Collects the phases of the copies to secondary repositories of every run of a backup.
+#[derive(Debug, Default)]
+pub struct CopyPhases(Vec<(String, Vec<BackupPhase>)>);
+
+impl CopyPhases {
+    /// Records the phase of every copy of a run from the latest pod of its last job.
+    ///
+    /// Copies that did not finish before the job failed, e.g. because the backup failed, are
+    /// failed. Without a job, e.g. once it was deleted after finishing, the finished phase in
+    /// `recorded` is kept.
+    pub fn record(
+        &mut self,
+        names: &[String],
+        job: Option<&Job>,
+        pod: Option<&Pod>,
+        recorded: Option<&[CopyStatus]>,
+    ) {
+        let failed = job.is_some_and(|job| job_phase(job) == BackupPhase::Failed);
+        for name in names {
+            let phase = match (job, pod) {
+                (Some(_), Some(pod)) => container_phase(pod, &copy_container_name(name)),
+                (Some(_), None) => BackupPhase::Pending,
+                (None, _) => recorded_copy_phase(recorded, name),
+            };
+            let phase = match phase {
+                BackupPhase::Pending | BackupPhase::Running if failed => BackupPhase::Failed,
+                phase => phase,
+            };
+            match self.0.iter_mut().find(|(n, _)| n == name) {
+                Some((_, phases)) => phases.push(phase),
+                None => self.0.push((name.clone(), vec![phase])),
+            }
+        }
+    }
+
+    /// Gets the status of every copy, combining the phases of every run.
+    pub fn statuses(self) -> Option<Vec<CopyStatus>> {
+        (!self.0.is_empty()).then(|| {
+            self.0
+                .into_iter()
+                .map(|(name, phases)| CopyStatus {
+                    name,
+                    phase: aggregate_phase(&phases),
+                })
+                .collect()
+        })
+    }
+}
+
+/// Gets the finished phase recorded for a copy, or [`BackupPhase::Pending`] if it had not
+/// finished.
+fn recorded_copy_phase(recorded: Option<&[CopyStatus]>, name: &str) -> BackupPhase {
+    let phase = recorded
+        .and_then(|c| c.iter().find(|c| c.name == name))
+        .map(|c| &c.phase);
+    match phase {
+        Some(phase @ (BackupPhase::Completed | BackupPhase::Failed)) => phase.clone(),
+        _ => BackupPhase::Pending,
+    }
+}
+
+impl BackupJobSpec {
+    /// Mounts the secret holding the Kubernetes manifests to backup.
+    pub fn with_manifests(mut self, secret_name: impl Into<String>) -> Self {
+        self.volume_mounts.push(VolumeMount {
+            mount_path: "/resticprofile/manifests.yaml".to_owned(),
+            name: "manifests".to_owned(),
+            sub_path: Some("manifests.yaml".to_owned()),
+            read_only: Some(true),
+            ..Default::default()
+        });
+        self.volumes.push(Volume {
+            name: "manifests".to_owned(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(secret_name.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        self
+    }
+}
+
+/// Security context of the backup container when none is given.
+///
+/// Restic runs as a non-root user in a way that is compatible with the `restricted` Pod Security
+/// Standard, unless it needs root access to read files owned by other users.
+fn default_security_context(rpcfg: &ResticProfileConfig) -> SecurityContext {
+    let mut ctx = SecurityContext {
+        allow_privilege_escalation: Some(false),
+        capabilities: Some(Capabilities {
+            drop: Some(vec!["ALL".to_owned()]),
+            add: None,
+        }),
+        seccomp_profile: Some(SeccompProfile {
+            type_: "RuntimeDefault".to_owned(),
+            localhost_profile: None,
+        }),
+        ..Default::default()
+    };
+
+    if rpcfg.needs_root_access.unwrap_or_default() {
+        ctx.run_as_non_root = Some(false);
+        ctx.run_as_user = Some(0);
+        ctx.run_as_group = Some(0);
+        if let Some(caps) = &mut ctx.capabilities {
+            caps.add = Some(vec!["DAC_READ_SEARCH".to_owned()]);
+        }
+    } else {
+        // The image runs as root, so pick a user unless the pod already sets one
+        let pod_ctx = rpcfg.pod_security_context.as_ref();
+        ctx.run_as_non_root = Some(true);
+        if pod_ctx.is_none_or(|c| c.run_as_user.is_none()) {
+            ctx.run_as_user = Some(NON_ROOT_USER);
+        }
+        if pod_ctx.is_none_or(|c| c.run_as_group.is_none()) {
+            ctx.run_as_group = Some(NON_ROOT_USER);
+        }
+    }
+
+    ctx
+}
+
 fn get_image(cfg: &mut ResticProfileConfig) -> String {
     cfg.image.take().unwrap_or_else(|| {
         format!(
@@ -122,6 +574,22 @@ fn fill_env(backup: &BackupSpec, rpcfg: &mut ResticProfileConfig) -> Vec<EnvVar>
         });
     }
 
+    // The snapshot host is read from the environment by resticprofile
+    let options = backup.restic.backup.as_ref();
+    if options.is_some_and(|o| o.host.is_none() && o.host_from == Some(HostSource::NodeName)) {
+        env.push(EnvVar {
+            name: resticprofile::NODE_NAME_ENV.to_owned(),
+            value_from: Some(EnvVarSource {
+                field_ref: Some(ObjectFieldSelector {
+                    field_path: "spec.nodeName".to_owned(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
     env
 }
 
@@ -164,9 +632,78 @@ fn fill_volume_mounts(
         ..Default::default()
     });
 
+    // Add volume mounts for the passwords of the secondary repositories
+    for repo in backup.restic.secondary_repositories.iter().flatten() {
+        let name = copy_container_name(&repo.name);
+        mounts.push(VolumeMount {
+            mount_path: resticprofile::copy_password_path(&repo.name),
+            name: name.clone(),
+            sub_path: Some(repo.password.key.clone()),
+            ..Default::default()
+        });
+        volumes.push(Volume {
+            name,
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(repo.password.name.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
+    // Add volume mount for the restic cache
+    if let Some(volume) = cache::volume(backup) {
+        mounts.push(VolumeMount {
+            mount_path: cache::CACHE_DIR.to_owned(),
+            name: volume.name.clone(),
+            ..Default::default()
+        });
+        volumes.push(volume);
+    }
+
+    // Add volume mount for the exclude and files-from lists
+    let lists = resticprofile::list_files(backup);
+    if !lists.is_empty() {
+        mounts.push(VolumeMount {
+            mount_path: resticprofile::LISTS_DIR.to_owned(),
+            name: "restic-lists".to_owned(),
+            read_only: Some(true),
+            ..Default::default()
+        });
+        let sources = lists
+            .into_iter()
+            .map(|(file, key)| VolumeProjection {
+                config_map: Some(ConfigMapProjection {
+                    name: key.name.clone(),
+                    items: Some(vec![KeyToPath {
+                        key: key.key.clone(),
+                        path: file,
+                        mode: None,
+                    }]),
+                    optional: key.optional,
+                }),
+                ..Default::default()
+            })
+            .collect();
+        volumes.push(Volume {
+            name: "restic-lists".to_owned(),
+            projected: Some(ProjectedVolumeSource {
+                sources: Some(sources),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
     // Add other volume mounts
     if let Some(vol_backup) = &backup.volume {
-        mounts.extend_from_slice(&vol_backup.mounts);
+        let read_only = vol_backup.read_only.unwrap_or(true);
+        mounts.extend(vol_backup.mounts.iter().cloned().map(|mut mount| {
+            if read_only {
+                mount.read_only = Some(true);
+            }
+            mount
+        }));
         volumes.extend_from_slice(&vol_backup.volumes);
     };
 
@@ -175,8 +712,17 @@ fn fill_volume_mounts(
 
 #[cfg(test)]
 mod tests {
-    use k8s_openapi::api::core::v1::SecretKeySelector;
-    use restic_crd::{Repository, RepositoryType, RestCredentials, ResticConfig, VolumeBackup};
+    use k8s_openapi::api::{
+        batch::v1::{JobCondition, JobStatus},
+        core::v1::{
+            ConfigMapKeySelector, ContainerStateTerminated, ContainerStatus,
+            PersistentVolumeClaimVolumeSource, PodStatus, SecretKeySelector,
+        },
+    };
+    use restic_crd::{
+        BackupOptions, Cache, PodMetadata, Repository, RepositoryType, ResourceBackup,
+        RestCredentials, ResticConfig, VolumeBackup,
+    };
 
     use super::*;
 
@@ -205,9 +751,22 @@ mod tests {
                         key: "password.txt".to_string(),
                         ..Default::default()
                     },
+                    version: None,
+                    max_concurrent_jobs: None,
                 })
                 .build(),
             volume: None,
+            stateful_set: None,
+            quiesce: None,
+            resources: None,
+            backoff_limit: None,
+            active_deadline_seconds: None,
+            ttl_seconds_after_finished: None,
+            pod_failure_policy: None,
+            pod_metadata: None,
+            common_labels: None,
+            common_annotations: None,
+            cache: None,
             restic_profile: Some(ResticProfileConfig {
                 image: Some("custom/restic:latest".to_string()),
                 version: Some("v1.0.0".to_string()),
@@ -246,6 +805,312 @@ mod tests {
         assert_eq!(job.image, "creativeprojects/resticprofile:latest");
     }
 
+    #[test]
+    fn test_with_manifests() {
+        let mut backup = create_backup();
+        backup.resources = Some(ResourceBackup {
+            kinds: Vec::new(),
+            selector: None,
+        });
+        let job = BackupJobSpec::new(&backup, CONFIG_NAME).with_manifests("test-manifests");
+
+        assert_eq!(
+            job.args,
+            Some(vec![
+                "--name".to_owned(),
+                DEFAULT_GROUP.to_owned(),
+                "backup".to_owned()
+            ])
+        );
+        assert_eq!(job.volume_mounts.len(), 3);
+        assert_eq!(job.volumes.len(), 3);
+    }
+
+    #[test]
+    fn test_with_queue() {
+        let backup = create_backup();
+        let job = BackupJobSpec::new(&backup, CONFIG_NAME);
+        assert!(!job.suspend);
+        assert!(!job.annotations.contains_key(queue::QUEUED_ANNOTATION));
+
+        let job = job.with_queue();
+        assert!(job.suspend);
+        assert_eq!(
+            job.annotations.get(queue::REPOSITORY_ANNOTATION).unwrap(),
+            "rest:https://example.com"
+        );
+        assert!(job.annotations.contains_key(queue::QUEUED_ANNOTATION));
+        assert!(!job
+            .annotations
+            .contains_key(queue::MAX_CONCURRENT_JOBS_ANNOTATION));
+
+        let mut backup = create_backup();
+        backup.restic.repository.max_concurrent_jobs = Some(2);
+        let job = BackupJobSpec::new(&backup, CONFIG_NAME);
+        assert_eq!(
+            job.annotations
+                .get(queue::MAX_CONCURRENT_JOBS_ANNOTATION)
+                .unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let mut backup = create_backup();
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        assert_eq!(pod.restart_policy.as_deref(), Some("OnFailure"));
+        assert_eq!(pod.containers[0].image_pull_policy, None);
+
+        let rpcfg = backup.restic_profile.as_mut().unwrap();
+        rpcfg.restart_policy = Some(RestartPolicy::Never);
+        rpcfg.image_pull_policy = Some(ImagePullPolicy::IfNotPresent);
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        assert_eq!(pod.restart_policy.as_deref(), Some("Never"));
+        assert_eq!(
+            pod.containers[0].image_pull_policy.as_deref(),
+            Some("IfNotPresent")
+        );
+    }
+
+    #[test]
+    fn test_cache_volume() {
+        let mut backup = create_backup();
+        backup.cache = Some(Cache {
+            persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                claim_name: "cache".to_owned(),
+                read_only: None,
+            }),
+            ..Default::default()
+        });
+
+        let (mounts, volumes) = fill_volume_mounts(&backup, CONFIG_NAME);
+        let mount = mounts
+            .iter()
+            .find(|m| m.name == cache::CACHE_VOLUME)
+            .unwrap();
+        assert_eq!(mount.mount_path, cache::CACHE_DIR);
+        let volume = volumes
+            .iter()
+            .find(|v| v.name == cache::CACHE_VOLUME)
+            .unwrap();
+        assert_eq!(
+            volume.persistent_volume_claim.as_ref().unwrap().claim_name,
+            "cache"
+        );
+    }
+
+    #[test]
+    fn test_lists_volume() {
+        let mut backup = create_backup();
+        let key = |name: &str| ConfigMapKeySelector {
+            name: name.to_owned(),
+            key: "list.txt".to_owned(),
+            optional: None,
+        };
+        backup.restic.backup = Some(
+            BackupOptions::builder()
+                .exclude_caches(false)
+                .exclude_file(vec![key("common"), key("team")])
+                .files_from(vec![key("files")])
+                .build(),
+        );
+
+        let (mounts, volumes) = fill_volume_mounts(&backup, CONFIG_NAME);
+        let mount = mounts.iter().find(|m| m.name == "restic-lists").unwrap();
+        assert_eq!(mount.mount_path, resticprofile::LISTS_DIR);
+        let volume = volumes.iter().find(|v| v.name == "restic-lists").unwrap();
+        let paths: Vec<_> = volume
+            .projected
+            .as_ref()
+            .unwrap()
+            .sources
+            .iter()
+            .flatten()
+            .map(|s| {
+                s.config_map.as_ref().unwrap().items.as_ref().unwrap()[0]
+                    .path
+                    .as_str()
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["exclude-file-0", "exclude-file-1", "files-from-0"]
+        );
+    }
+
+    #[test]
+    fn test_copy_containers() {
+        let mut backup = create_backup();
+        backup.restic.secondary_repositories = Some(vec![SecondaryRepository::builder()
+            .name("offsite".to_owned())
+            .uri("https://offsite.example.com".to_owned())
+            .password(SecretKeySelector {
+                name: "offsite".to_owned(),
+                key: "password".to_owned(),
+                optional: None,
+            })
+            .rest_credentials(RestCredentials {
+                username: Default::default(),
+                password: Default::default(),
+            })
+            .build()]);
+
+        let job: JobSpec = BackupJobSpec::new(&backup, CONFIG_NAME).into();
+        let pod = job.template.spec.unwrap();
+        // The backup finishes before the copies start
+        let init_containers = pod.init_containers.unwrap();
+        assert_eq!(init_containers.last().unwrap().name, CONTAINER_NAME);
+        let copy = &pod.containers[0];
+        assert_eq!(copy.name, "restic-copy-offsite");
+        assert_eq!(
+            copy.args.as_deref().unwrap(),
+            ["--name", "copy-offsite", "copy"]
+        );
+        let env = copy.env.as_ref().unwrap();
+        assert!(env
+            .iter()
+            .any(|e| e.name == resticprofile::COPY_PASSWORD_ENV));
+        assert!(copy
+            .volume_mounts
+            .iter()
+            .flatten()
+            .any(|m| m.mount_path == resticprofile::copy_password_path("offsite")));
+    }
+
+    #[test]
+    fn test_container_phase() {
+        let mut pod = Pod::default();
+        assert_eq!(container_phase(&pod, "copy"), BackupPhase::Pending);
+
+        let state = |state| {
+            Some(PodStatus {
+                container_statuses: Some(vec![ContainerStatus {
+                    name: "copy".to_owned(),
+                    state: Some(state),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            })
+        };
+        pod.status = state(ContainerState {
+            running: Some(Default::default()),
+            ..Default::default()
+        });
+        assert_eq!(container_phase(&pod, "copy"), BackupPhase::Running);
+        pod.status = state(ContainerState {
+            terminated: Some(ContainerStateTerminated {
+                exit_code: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(container_phase(&pod, "copy"), BackupPhase::Failed);
+        pod.status = state(ContainerState {
+            terminated: Some(Default::default()),
+            ..Default::default()
+        });
+        assert_eq!(container_phase(&pod, "copy"), BackupPhase::Completed);
+    }
+
+    #[test]
+    fn test_extra_containers() {
+        let mut backup = create_backup();
+        let rpcfg = backup.restic_profile.as_mut().unwrap();
+        rpcfg.init_containers = Some(vec![Container {
+            name: "chown".to_owned(),
+            ..Default::default()
+        }]);
+        rpcfg.extra_containers = Some(vec![Container {
+            name: "vault-agent".to_owned(),
+            ..Default::default()
+        }]);
+
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        assert_eq!(pod.init_containers.unwrap()[0].name, "chown");
+        let names: Vec<_> = pod.containers.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec![CONTAINER_NAME, "vault-agent"]);
+    }
+
+    #[test]
+    fn test_pod_metadata() {
+        let mut backup = create_backup();
+        backup.pod_metadata = Some(PodMetadata {
+            labels: Some(BTreeMap::from([("team".to_owned(), "db".to_owned())])),
+            annotations: Some(BTreeMap::from([(
+                "sidecar.istio.io/inject".to_owned(),
+                "false".to_owned(),
+            )])),
+        });
+        let labels = Labels::new("test")
+            .with_labels([("team".to_owned(), "storage".to_owned())])
+            .with_annotations([("owner".to_owned(), "ops".to_owned())]);
+
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME).with_labels(&labels));
+        let meta = spec.template.metadata.unwrap();
+        let pod_labels = meta.labels.unwrap();
+        assert_eq!(pod_labels["team"], "db");
+        assert_eq!(pod_labels["app.kubernetes.io/name"], "test");
+        let pod_annotations = meta.annotations.unwrap();
+        assert_eq!(pod_annotations["sidecar.istio.io/inject"], "false");
+        assert_eq!(pod_annotations["owner"], "ops");
+    }
+
+    #[test]
+    fn test_job_lifecycle() {
+        let mut backup = create_backup();
+        backup.backoff_limit = Some(2);
+        backup.active_deadline_seconds = Some(3600);
+        backup.pod_failure_policy = Some(PodFailurePolicy {
+            fail_job_exit_codes: Some(vec![12, 3, 3, 0]),
+            ignore_exit_codes: Some(vec![11]),
+            ignore_disruptions: None,
+        });
+
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        assert_eq!(spec.backoff_limit, Some(2));
+        assert_eq!(spec.active_deadline_seconds, Some(3600));
+        assert_eq!(spec.ttl_seconds_after_finished, None);
+        assert_eq!(
+            spec.template.spec.unwrap().restart_policy.as_deref(),
+            Some("Never")
+        );
+
+        let rules = spec.pod_failure_policy.unwrap().rules;
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].action, "Ignore");
+        assert!(rules[0].on_pod_conditions.is_some());
+        assert_eq!(rules[1].action, "FailJob");
+        assert_eq!(rules[1].on_exit_codes.as_ref().unwrap().values, vec![3, 12]);
+        assert_eq!(rules[2].on_exit_codes.as_ref().unwrap().values, vec![11]);
+    }
+
+    #[test]
+    fn test_pod_scheduling() {
+        let mut backup = create_backup();
+        let rpcfg = backup.restic_profile.as_mut().unwrap();
+        rpcfg.tolerations = Some(vec![Toleration {
+            key: Some("storage".to_owned()),
+            operator: Some("Exists".to_owned()),
+            effect: Some("NoSchedule".to_owned()),
+            ..Default::default()
+        }]);
+        rpcfg.priority_class_name = Some("backup".to_owned());
+        rpcfg.image_pull_secrets = Some(vec![LocalObjectReference {
+            name: "registry".to_owned(),
+        }]);
+
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        assert_eq!(pod.tolerations.unwrap()[0].key.as_deref(), Some("storage"));
+        assert_eq!(pod.priority_class_name.as_deref(), Some("backup"));
+        assert_eq!(pod.image_pull_secrets.unwrap()[0].name, "registry");
+        assert_eq!(pod.host_network, None);
+    }
+
     #[test]
     fn test_fill_env_with_no_credentials() {
         let mut backup = create_backup();
@@ -279,11 +1144,192 @@ mod tests {
                 name: "data-volume".to_string(),
                 ..Default::default()
             }],
+            read_only: None,
+            tags: None,
+            path_prefix: None,
         });
         let config_name = "test-config";
         let (volume_mounts, volumes) = fill_volume_mounts(&backup, config_name);
 
         assert_eq!(volume_mounts.len(), 3);
         assert_eq!(volumes.len(), 3);
+        assert_eq!(volume_mounts[2].read_only, Some(true));
+
+        backup.volume.as_mut().unwrap().read_only = Some(false);
+        let (volume_mounts, _) = fill_volume_mounts(&backup, config_name);
+        assert_eq!(volume_mounts[2].read_only, None);
+    }
+
+    #[test]
+    fn test_security_context() {
+        let mut backup = create_backup();
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        let ctx = pod.containers[0].security_context.clone().unwrap();
+        assert_eq!(ctx.run_as_non_root, Some(true));
+        assert_eq!(ctx.run_as_user, Some(NON_ROOT_USER));
+        assert_eq!(ctx.allow_privilege_escalation, Some(false));
+        assert_eq!(ctx.capabilities.unwrap().drop, Some(vec!["ALL".to_owned()]));
+        assert_eq!(ctx.seccomp_profile.unwrap().type_, "RuntimeDefault");
+
+        // The pod's user is not overridden
+        let rpcfg = backup.restic_profile.as_mut().unwrap();
+        rpcfg.pod_security_context = Some(PodSecurityContext {
+            run_as_user: Some(1000),
+            fs_group: Some(1000),
+            ..Default::default()
+        });
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let pod = spec.template.spec.unwrap();
+        assert_eq!(pod.security_context.unwrap().fs_group, Some(1000));
+        assert_eq!(
+            pod.containers[0]
+                .security_context
+                .as_ref()
+                .unwrap()
+                .run_as_user,
+            None
+        );
+
+        let rpcfg = backup.restic_profile.as_mut().unwrap();
+        rpcfg.needs_root_access = Some(true);
+        let spec = JobSpec::from(BackupJobSpec::new(&backup, CONFIG_NAME));
+        let ctx = spec.template.spec.unwrap().containers[0]
+            .security_context
+            .clone()
+            .unwrap();
+        assert_eq!(ctx.run_as_user, Some(0));
+        assert_eq!(
+            ctx.capabilities.unwrap().add,
+            Some(vec!["DAC_READ_SEARCH".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_job_phase() {
+        let mut job = Job::default();
+        assert_eq!(job_phase(&job), BackupPhase::Pending);
+
+        job.status = Some(JobStatus {
+            active: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(job_phase(&job), BackupPhase::Running);
+
+        job.status = Some(JobStatus {
+            conditions: Some(vec![JobCondition {
+                type_: "Failed".to_owned(),
+                status: "True".to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        });
+        assert_eq!(job_phase(&job), BackupPhase::Failed);
+
+        job.status = Some(JobStatus {
+            succeeded: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(job_phase(&job), BackupPhase::Completed);
+    }
+
+    #[test]
+    fn test_aggregate_phase() {
+        use BackupPhase::*;
+
+        assert_eq!(aggregate_phase(&[Completed, Completed]), Completed);
+        assert_eq!(aggregate_phase(&[Completed, Running]), Running);
+        assert_eq!(aggregate_phase(&[Pending, Failed, Running]), Failed);
+        assert_eq!(aggregate_phase(&[Pending, Completed]), Pending);
+        assert_eq!(aggregate_phase(&[]), Pending);
+    }
+
+    #[test]
+    fn test_copy_phases() {
+        let names = vec!["offsite".to_owned(), "nas".to_owned()];
+        let pod = Pod {
+            status: Some(PodStatus {
+                container_statuses: Some(vec![ContainerStatus {
+                    name: copy_container_name("offsite"),
+                    state: Some(ContainerState {
+                        terminated: Some(ContainerStateTerminated {
+                            exit_code: 1,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let job = Job::default();
+
+        let mut copies = CopyPhases::default();
+        copies.record(&names, Some(&job), Some(&pod), None);
+        copies.record(&names, Some(&job), None, None);
+        assert_eq!(
+            copies.statuses(),
+            Some(vec![
+                CopyStatus::builder()
+                    .name("offsite".to_owned())
+                    .phase(BackupPhase::Failed)
+                    .build(),
+                CopyStatus::builder()
+                    .name("nas".to_owned())
+                    .phase(BackupPhase::Pending)
+                    .build(),
+            ])
+        );
+
+        // Without a job, only finished phases are kept
+        let recorded = [
+            CopyStatus::builder()
+                .name("offsite".to_owned())
+                .phase(BackupPhase::Completed)
+                .build(),
+            CopyStatus::builder()
+                .name("nas".to_owned())
+                .phase(BackupPhase::Running)
+                .build(),
+        ];
+        let mut copies = CopyPhases::default();
+        copies.record(&names, None, None, Some(&recorded));
+        assert_eq!(
+            copies.statuses(),
+            Some(vec![
+                CopyStatus::builder()
+                    .name("offsite".to_owned())
+                    .phase(BackupPhase::Completed)
+                    .build(),
+                CopyStatus::builder()
+                    .name("nas".to_owned())
+                    .phase(BackupPhase::Pending)
+                    .build(),
+            ])
+        );
+
+        // The copies never start once the backup failed
+        let failed = Job {
+            status: Some(JobStatus {
+                conditions: Some(vec![JobCondition {
+                    type_: "Failed".to_owned(),
+                    status: "True".to_owned(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut copies = CopyPhases::default();
+        copies.record(&names, Some(&failed), Some(&pod), None);
+        assert!(copies
+            .statuses()
+            .unwrap()
+            .iter()
+            .all(|c| c.phase == BackupPhase::Failed));
+
+        assert_eq!(CopyPhases::default().statuses(), None);
     }
 }
@@ -1,15 +1,30 @@
+// The finalizer checks predate `Option::is_none_or`
+#![allow(clippy::unnecessary_map_or)]
+
+use config::Config;
 use kube::Client;
-use tracing::{info, level_filters::LevelFilter};
+use tracing::{error, info, level_filters::LevelFilter};
 use tracing_subscriber::EnvFilter;
 
 mod backup;
+mod cache;
+mod config;
 mod context;
 mod deploy;
 mod error;
 mod finalizer;
+mod hash;
 mod jobspec;
+mod manifests;
+mod node;
+mod queue;
+mod quiesce;
+mod replica;
+mod repository;
 mod resticprofile;
 mod schedule;
+mod status;
+mod webhook;
 
 pub use error::Error;
 
@@ -28,11 +43,22 @@ async fn main() {
         .await
         .expect("Expected a valid KUBECONFIG environment variable.");
 
+    let config = Config::from_env().expect("Expected valid operator settings.");
+
     info!("Starting up...");
 
     let signal = tokio::signal::ctrl_c();
-    let backup_fut = tokio::spawn(backup::run_controller(k8s_client.clone()));
-    let schedule_fut = tokio::spawn(schedule::run_controller(k8s_client.clone()));
+    let backup_fut = tokio::spawn(backup::run_controller(k8s_client.clone(), config.clone()));
+    let schedule_fut = tokio::spawn(schedule::run_controller(k8s_client.clone(), config.clone()));
+    let node_fut = tokio::spawn(node::run_controller(k8s_client.clone(), config.clone()));
+    // Repositories can limit their jobs on their own, so the queue always runs
+    let queue_fut = queue::run(k8s_client.clone(), config.clone());
+    let webhook_fut = async {
+        match config.webhook_port {
+            Some(port) => webhook::run(k8s_client.clone(), config.clone(), port).await,
+            None => std::future::pending().await,
+        }
+    };
 
     info!("Controllers started.");
 
@@ -40,6 +66,15 @@ async fn main() {
         _ = signal => {}
         _ = backup_fut => {}
         _ = schedule_fut => {}
+        _ = node_fut => {}
+        _ = queue_fut => {}
+        result = webhook_fut => {
+            // Exit with a failure so the pod is restarted instead of running without the webhook
+            if let Err(err) = result {
+                error!(%err, "Webhook server failed");
+                std::process::exit(1);
+            }
+        }
     }
 
     info!("Successfully shut down.")
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+    chrono::{DateTime, Duration, Utc},
+};
+use kube::{
+    api::{DynamicObject, ListParams, ObjectMeta, Patch, PatchParams},
+    core::{GroupVersionKind, Selector},
+    discovery, Api, Client, ResourceExt,
+};
+use restic_crd::ResourceBackup;
+use serde_json::json;
+
+use crate::{
+    deploy::{Deployable, Labels},
+    Error,
+};
+
+const MANIFESTS_KEY: &str = "manifests.yaml";
+/// Annotation on the secret storing when the manifests were last collected.
+const COLLECTED_AT_ANNOTATION: &str = "restic.anshulg.com/collected-at";
+/// Maximum size of the data of a Secret.
+const MAX_SECRET_SIZE: usize = 1024 * 1024;
+/// Minimum time between two collections of the manifests.
+const REFRESH_INTERVAL: Duration = Duration::minutes(5);
+
+/// Secret holding the YAML manifests of the resources selected for backup.
+#[derive(Debug, Clone)]
+pub struct Manifests {
+    name: String,
+    ns: String,
+    resources: ResourceBackup,
+    /// Manifests collected by [`Manifests::check`], reused on creation.
+    collected: Option<String>,
+}
+
+impl Manifests {
+    pub fn new(ns: String, name: impl AsRef<str>, resources: ResourceBackup) -> Self {
+        Self {
+            name: secret_name(name.as_ref()),
+            ns,
+            resources,
+            collected: None,
+        }
+    }
+
+    /// Serializes every selected object in the namespace to a multi-document YAML string.
+    async fn collect(&self, client: Client) -> Result<String, Error> {
+        let mut params = ListParams::default();
+        if let Some(selector) = &self.resources.selector {
+            params = params.labels_from(&Selector::try_from(selector.clone())?);
+        }
+
+        let mut docs = Vec::new();
+        for kind in &self.resources.kinds {
+            let gvk = GroupVersionKind::gvk(&kind.group, &kind.version, &kind.kind);
+            let (resource, _) = discovery::pinned_kind(&client, &gvk).await?;
+            let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), &self.ns, &resource);
+
+            let mut objects = api.list(&params).await?.items;
+            objects.sort_by_key(ResourceExt::name_any);
+            for mut object in objects {
+                object.types = Some(kube::core::TypeMeta {
+                    api_version: resource.api_version.clone(),
+                    kind: resource.kind.clone(),
+                });
+                docs.push(serde_yaml::to_string(&strip(object))?);
+            }
+        }
+
+        let manifests = docs.join("---\n");
+        check_size(&self.name, &manifests)?;
+        Ok(manifests)
+    }
+
+    /// Checks that the manifests fit in the secret, keeping them for the secret's creation.
+    pub async fn check(&mut self, client: Client) -> Result<(), Error> {
+        self.collected = Some(self.collect(client).await?);
+        Ok(())
+    }
+
+    /// Re-collects the manifests if they were last collected more than [`REFRESH_INTERVAL`] ago.
+    pub async fn refresh(&self, client: Client) -> Result<(), Error> {
+        let api: Api<Secret> = Api::namespaced(client.clone(), &self.ns);
+        let Some(secret) = api.get_opt(&self.name).await? else {
+            return Ok(());
+        };
+
+        let stale = secret
+            .annotations()
+            .get(COLLECTED_AT_ANNOTATION)
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .is_none_or(|t| Utc::now() - t.with_timezone(&Utc) > REFRESH_INTERVAL);
+        if !stale {
+            return Ok(());
+        }
+
+        let manifests = self.collect(client).await?;
+        let patch = json!({
+            "metadata": {
+                "annotations": { COLLECTED_AT_ANNOTATION: Utc::now().to_rfc3339() }
+            },
+            "stringData": { MANIFESTS_KEY: manifests }
+        });
+        api.patch(&self.name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+        Ok(())
+    }
+}
+
+impl Deployable for Manifests {
+    type Error = Error;
+
+    async fn create<O>(&self, client: Client, owner: &O, labels: Labels) -> Result<(), Self::Error>
+    where
+        O: kube::Resource<DynamicType = ()> + Send + Sync,
+    {
+        let manifests = match &self.collected {
+            Some(manifests) => manifests.clone(),
+            None => self.collect(client.clone()).await?,
+        };
+
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                namespace: Some(self.ns.clone()),
+                labels: Some(labels.to_labels()),
+                annotations: Some(
+                    labels
+                        .with_annotations([(
+                            COLLECTED_AT_ANNOTATION.to_owned(),
+                            Utc::now().to_rfc3339(),
+                        )])
+                        .to_annotations(),
+                ),
+                owner_references: O::meta(owner).uid.clone().map(|uid| {
+                    vec![OwnerReference {
+                        api_version: O::api_version(&()).into_owned(),
+                        block_owner_deletion: Some(true),
+                        controller: Some(true),
+                        kind: O::kind(&()).into_owned(),
+                        name: O::name_any(owner),
+                        uid,
+                    }]
+                }),
+                ..ObjectMeta::default()
+            },
+            string_data: Some(BTreeMap::from([(MANIFESTS_KEY.to_owned(), manifests)])),
+            ..Secret::default()
+        };
+
+        let api: Api<Secret> = Api::namespaced(client, &self.ns);
+        api.create(&Default::default(), &secret).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, client: Client) -> Result<(), Self::Error> {
+        let api: Api<Secret> = Api::namespaced(client, &self.ns);
+        if api.get_opt(&self.name).await?.is_some() {
+            api.delete(&self.name, &Default::default()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the manifests fit in the secret, which the API server would reject otherwise.
+fn check_size(name: &str, manifests: &str) -> Result<(), Error> {
+    if MANIFESTS_KEY.len() + manifests.len() > MAX_SECRET_SIZE {
+        return Err(Error::ManifestsTooLarge {
+            name: name.to_owned(),
+            size: manifests.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Name of the secret holding the manifests of the given backup run.
+pub fn secret_name(name: &str) -> String {
+    format!("{name}-manifests")
+}
+
+/// Removes the status and server-populated metadata from an object.
+fn strip(mut object: DynamicObject) -> DynamicObject {
+    object.metadata.creation_timestamp = None;
+    object.metadata.generation = None;
+    object.metadata.managed_fields = None;
+    object.metadata.resource_version = None;
+    object.metadata.uid = None;
+    if let Some(data) = object.data.as_object_mut() {
+        data.remove("status");
+    }
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::ConfigMap;
+    use kube::api::ApiResource;
+
+    use super::*;
+
+    #[test]
+    fn test_strip() {
+        let mut object = DynamicObject::new("test", &ApiResource::erase::<ConfigMap>(&()))
+            .data(json!({ "data": { "key": "value" }, "status": {} }));
+        object.metadata.uid = Some("1234".to_owned());
+        object.metadata.resource_version = Some("1".to_owned());
+
+        let value = serde_json::to_value(strip(object)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "metadata": { "name": "test" },
+                "data": { "key": "value" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_size() {
+        assert!(check_size("test-manifests", "kind: ConfigMap\n").is_ok());
+
+        let manifests = "a".repeat(MAX_SECRET_SIZE);
+        assert!(matches!(
+            check_size("test-manifests", &manifests),
+            Err(Error::ManifestsTooLarge { size, .. }) if size == MAX_SECRET_SIZE
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+use kube::{Client, ResourceExt};
+use restic_crd::{BackupPhase, NodeBackup, NodeStatus};
+
+use super::{run_name, scheduled_backup};
+use crate::{
+    backup::job::{latest_pod, BackupJob},
+    cache::CacheClaim,
+    config::Config,
+    deploy::{Deployable, Labels},
+    jobspec::{copy_names, job_phase, CopyPhases},
+    resticprofile::ResticProfile,
+    schedule::cronjob::BackupCronJob,
+    Error,
+};
+
+#[derive(Debug, Clone)]
+pub struct NodeBackupDeployment {
+    runs: Vec<NodeRun>,
+}
+
+#[derive(Debug, Clone)]
+struct NodeRun {
+    node_name: String,
+    profile: ResticProfile,
+    cache: Option<CacheClaim>,
+    job: NodeJob,
+    /// Names of the secondary repositories the snapshots are copied to
+    copies: Vec<String>,
+}
+
+/// A node is backed up once by a job, or on a schedule by a cronjob.
+#[derive(Debug, Clone)]
+enum NodeJob {
+    Job(BackupJob),
+    CronJob(BackupCronJob),
+}
+
+impl NodeBackupDeployment {
+    pub fn new(ns: String, backup: &NodeBackup, nodes: &[String], config: &Config) -> Self {
+        let spec = super::node_spec(&backup.spec);
+        let runs = nodes
+            .iter()
+            .map(|node_name| {
+                let name = run_name(&backup.name_any(), node_name);
+                let mut profile = ResticProfile::new(ns.clone(), name.clone(), &spec);
+                // Snapshots of a node are grouped by node unless configured otherwise
+                let options = spec.restic.backup.as_ref();
+                if options.is_none_or(|o| o.host.is_none() && o.host_from.is_none()) {
+                    profile = profile.with_host(node_name);
+                }
+                let cache = CacheClaim::new(ns.clone(), &spec);
+                let job = match &backup.spec.schedule {
+                    Some(_) => {
+                        let scheduled = scheduled_backup(backup, &spec);
+                        let mut job =
+                            BackupCronJob::new(ns.clone(), name, &scheduled, &spec, profile.name())
+                                .with_node_name(node_name);
+                        if config.queue_enabled(&spec.restic.repository) {
+                            job = job.with_queue();
+                        }
+                        NodeJob::CronJob(job)
+                    }
+                    None => {
+                        let mut job = BackupJob::new(ns.clone(), name, &spec, profile.name())
+                            .with_node_name(node_name);
+                        if config.queue_enabled(&spec.restic.repository) {
+                            job = job.with_queue();
+                        }
+                        NodeJob::Job(job)
+                    }
+                };
+                NodeRun {
+                    node_name: node_name.clone(),
+                    profile,
+                    cache,
+                    job,
+                    copies: copy_names(&spec),
+                }
+            })
+            .collect();
+        Self { runs }
+    }
+
+    /// Gets the status of every node's sub-resources.
+    ///
+    /// The phase of a scheduled node backup is the phase of its cronjob's last job. Jobs deleted
+    /// after finishing keep the finished phase recorded in the previous status.
+    pub async fn status(
+        &self,
+        client: Client,
+        recorded: &[NodeStatus],
+    ) -> Result<Vec<NodeStatus>, Error> {
+        let mut nodes = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            let (job, cron_job, last_job) = match &run.job {
+                NodeJob::Job(job) => (
+                    Some(job.name().to_owned()),
+                    None,
+                    job.get(client.clone()).await?,
+                ),
+                NodeJob::CronJob(job) => (
+                    None,
+                    Some(job.name().to_owned()),
+                    job.last_job(client.clone()).await?,
+                ),
+            };
+            let recorded = recorded.iter().find(|n| n.node_name == run.node_name);
+            let phase = match &last_job {
+                Some(job) => job_phase(job),
+                None => recorded
+                    .map(|n| n.phase.clone())
+                    .filter(|p| matches!(p, BackupPhase::Completed | BackupPhase::Failed))
+                    .unwrap_or_default(),
+            };
+
+            let mut copies = CopyPhases::default();
+            if !run.copies.is_empty() {
+                let pod = match &last_job {
+                    Some(job) => latest_pod(client.clone(), job).await?,
+                    None => None,
+                };
+                copies.record(
+                    &run.copies,
+                    last_job.as_ref(),
+                    pod.as_ref(),
+                    recorded.and_then(|n| n.copies.as_deref()),
+                );
+            }
+            nodes.push(NodeStatus {
+                node_name: run.node_name.clone(),
+                config_map: Some(run.profile.name().to_owned()),
+                job,
+                cron_job,
+                phase,
+                copies: copies.statuses(),
+            });
+        }
+        Ok(nodes)
+    }
+}
+
+impl Deployable for NodeBackupDeployment {
+    type Error = Error;
+
+    async fn create<O>(&self, client: Client, owner: &O, labels: Labels) -> Result<(), Self::Error>
+    where
+        O: kube::Resource<DynamicType = ()> + Send + Sync,
+    {
+        for run in &self.runs {
+            if let Some(cache) = &run.cache {
+                cache.create(client.clone(), owner, labels.clone()).await?;
+            }
+            run.profile
+                .create(client.clone(), owner, labels.clone())
+                .await?;
+            match &run.job {
+                NodeJob::Job(job) => job.create(client.clone(), owner, labels.clone()).await?,
+                NodeJob::CronJob(job) => job.create(client.clone(), owner, labels.clone()).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, client: Client) -> Result<(), Self::Error> {
+        for run in &self.runs {
+            run.profile.delete(client.clone()).await?;
+            match &run.job {
+                NodeJob::Job(job) => job.delete(client.clone()).await?,
+                NodeJob::CronJob(job) => job.delete(client.clone()).await?,
+            }
+            if let Some(cache) = &run.cache {
+                cache.delete(client.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use deploy::NodeBackupDeployment;
+use futures::StreamExt;
+use k8s_openapi::api::{
+    batch::v1::Job,
+    core::v1::{HostPathVolumeSource, Node, Volume, VolumeMount},
+};
+use kube::{
+    api::{ListParams, ObjectMeta},
+    core::Selector,
+    runtime::{controller::Action, watcher::Config, Controller},
+    Api, Client, Resource, ResourceExt,
+};
+use restic_crd::{
+    BackupSpec, NodeBackup, NodeBackupSpec, NodeStatus, ScheduledBackup, ScheduledBackupSpec,
+    VolumeBackup,
+};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    config,
+    context::ContextData,
+    deploy::{Deployable, Labels},
+    finalizer::{self, FINALIZER},
+    hash, schedule, status, Error,
+};
+
+mod deploy;
+
+/// Directory the host paths are mounted under in the backup pods.
+const HOST_MOUNT_PATH: &str = "/host";
+
+pub async fn run_controller(client: Client, config: config::Config) {
+    let crd_api: Api<NodeBackup> = Api::all(client.clone());
+    let context: Arc<ContextData> = Arc::new(ContextData::new(client, config));
+
+    Controller::new(crd_api, Config::default())
+        .owns(Api::<Job>::all(context.client.clone()), Config::default())
+        .run(reconcile, on_error, context)
+        .for_each(|reconciliation_result| async move {
+            match reconciliation_result {
+                Ok(echo_resource) => {
+                    info!("Reconciliation successful. Resource: {:?}", echo_resource);
+                }
+                Err(reconciliation_err) => {
+                    error!(%reconciliation_err, "Reconciliation error")
+                }
+            }
+        })
+        .await;
+}
+
+async fn reconcile(backup: Arc<NodeBackup>, context: Arc<ContextData>) -> Result<Action, Error> {
+    let client = context.client.clone();
+
+    let ns = backup.namespace().ok_or(Error::MissingNamespace)?;
+    let name = backup.name_any();
+    let api = Api::<NodeBackup>::namespaced(client.clone(), &ns);
+
+    match determine_action(&backup) {
+        NodeBackupAction::Create => {
+            // Kubernetes would reject the CronJobs, so wait for the spec to be fixed
+            if let Err(err) = validate(&backup) {
+                warn!(name, %err, "Invalid schedule");
+                let mut conditions = backup
+                    .status
+                    .as_ref()
+                    .and_then(|s| s.conditions.clone())
+                    .unwrap_or_default();
+                let condition = status::condition(
+                    status::READY,
+                    false,
+                    "InvalidSchedule",
+                    err.to_string(),
+                    backup.metadata.generation,
+                );
+                if status::set_condition(&mut conditions, condition) {
+                    status::patch(&api, &name, &json!({ "conditions": conditions })).await?;
+                }
+                return Ok(Action::await_change());
+            }
+
+            finalizer::add(&api, &name).await?;
+            sync(client, &context.config, &backup).await?;
+
+            let mut conditions = backup
+                .status
+                .as_ref()
+                .and_then(|s| s.conditions.clone())
+                .unwrap_or_default();
+            let condition = status::condition(
+                status::READY,
+                true,
+                "Deployed",
+                "Created the node backup jobs",
+                backup.metadata.generation,
+            );
+            if status::set_condition(&mut conditions, condition) {
+                status::patch(&api, &name, &json!({ "conditions": conditions })).await?;
+            }
+            Ok(Action::requeue(Duration::from_secs(10)))
+        }
+        NodeBackupAction::Delete => {
+            let nodes: Vec<_> = recorded_nodes(&backup)
+                .iter()
+                .map(|n| n.node_name.clone())
+                .collect();
+            let deployment = NodeBackupDeployment::new(ns, &backup, &nodes, &context.config);
+            deployment.delete(client).await?;
+
+            finalizer::remove(&api, &name).await?;
+            Ok(Action::await_change())
+        }
+        NodeBackupAction::Noop => {
+            sync(client, &context.config, &backup).await?;
+            Ok(Action::requeue(Duration::from_secs(10)))
+        }
+    }
+}
+
+/// Deploys the backup to new matching nodes, removes it from nodes that no longer match, and
+/// updates the status of every node.
+async fn sync(client: Client, config: &config::Config, backup: &NodeBackup) -> Result<(), Error> {
+    let ns = backup.namespace().ok_or(Error::MissingNamespace)?;
+    let name = backup.name_any();
+    let recorded = recorded_nodes(backup);
+
+    let nodes = list_nodes(client.clone(), &backup.spec).await?;
+    let (added, removed) = diff_nodes(&recorded, &nodes);
+
+    if !removed.is_empty() {
+        info!(name, ?removed, "Removing node backups");
+        NodeBackupDeployment::new(ns.clone(), backup, &removed, config)
+            .delete(client.clone())
+            .await?;
+    }
+    if !added.is_empty() {
+        info!(name, ?added, "Adding node backups");
+        let labels = Labels::new(name.clone())
+            .with_labels(backup.labels().clone())
+            .with_common(&backup.spec.backup);
+        let deployment = NodeBackupDeployment::new(ns.clone(), backup, &added, config);
+        if let Err(err) = deployment.create(client.clone(), backup, labels).await {
+            // Clean up so the nodes are added again from scratch on the next reconcile
+            deployment.delete(client.clone()).await?;
+            return Err(err);
+        }
+    }
+
+    let deployment = NodeBackupDeployment::new(ns.clone(), backup, &nodes, config);
+    let statuses = deployment.status(client.clone(), &recorded).await?;
+    if statuses != recorded {
+        let api = Api::<NodeBackup>::namespaced(client, &ns);
+        status::patch(&api, &name, &json!({ "nodes": statuses })).await?;
+    }
+    Ok(())
+}
+
+/// Lists the names of the nodes matching the backup's node selector.
+async fn list_nodes(client: Client, spec: &NodeBackupSpec) -> Result<Vec<String>, Error> {
+    let mut params = ListParams::default();
+    if let Some(selector) = &spec.node_selector {
+        params = params.labels_from(&Selector::from_iter(selector.clone()));
+    }
+
+    let api: Api<Node> = Api::all(client);
+    let mut nodes: Vec<_> = api
+        .list(&params)
+        .await?
+        .into_iter()
+        .map(|n| n.name_any())
+        .collect();
+    nodes.sort();
+    Ok(nodes)
+}
+
+/// Splits the difference between the recorded and current nodes into added and removed nodes.
+fn diff_nodes(recorded: &[NodeStatus], nodes: &[String]) -> (Vec<String>, Vec<String>) {
+    let recorded: BTreeSet<_> = recorded.iter().map(|n| &n.node_name).collect();
+    let current: BTreeSet<_> = nodes.iter().collect();
+    let added = current
+        .difference(&recorded)
+        .map(|n| (*n).clone())
+        .collect();
+    let removed = recorded
+        .difference(&current)
+        .map(|n| (*n).clone())
+        .collect();
+    (added, removed)
+}
+
+/// Gets the nodes recorded in the status.
+fn recorded_nodes(backup: &NodeBackup) -> Vec<NodeStatus> {
+    backup
+        .status
+        .as_ref()
+        .and_then(|s| s.nodes.clone())
+        .unwrap_or_default()
+}
+
+/// Name of the sub-resources of a node's backup run.
+///
+/// Node names can be up to 253 characters long, so a hash is used to keep job names short.
+fn run_name(name: &str, node_name: &str) -> String {
+    format!("{name}-{:08x}", hash::fnv1a(node_name.as_bytes()))
+}
+
+/// Backup spec of every node, with the host paths mounted read-only under [`HOST_MOUNT_PATH`].
+pub fn node_spec(spec: &NodeBackupSpec) -> BackupSpec {
+    let mut backup = spec.backup.clone();
+    backup.stateful_set = None;
+    backup.quiesce = None;
+    backup.resources = None;
+
+    let vol_backup = backup.volume.get_or_insert_with(|| VolumeBackup {
+        mounts: Vec::new(),
+        volumes: Vec::new(),
+        read_only: None,
+        tags: None,
+        path_prefix: None,
+    });
+    // Snapshots hold the paths as they are on the host
+    vol_backup
+        .path_prefix
+        .get_or_insert_with(|| HOST_MOUNT_PATH.to_owned());
+
+    for (i, path) in spec.paths.iter().enumerate() {
+        let name = format!("host-{i}");
+        vol_backup.mounts.push(VolumeMount {
+            mount_path: format!("{HOST_MOUNT_PATH}/{}", path.trim_start_matches('/')),
+            name: name.clone(),
+            ..Default::default()
+        });
+        vol_backup.volumes.push(Volume {
+            name,
+            host_path: Some(HostPathVolumeSource {
+                path: path.clone(),
+                type_: None,
+            }),
+            ..Default::default()
+        });
+    }
+
+    // Host files are usually only readable by root
+    let rpcfg = backup.restic_profile.get_or_insert_with(Default::default);
+    rpcfg.needs_root_access.get_or_insert(true);
+
+    backup
+}
+
+/// Node cronjobs are deployed like the ones of a [`ScheduledBackup`] with the same name.
+fn scheduled_backup(backup: &NodeBackup, spec: &BackupSpec) -> ScheduledBackup {
+    ScheduledBackup {
+        metadata: ObjectMeta {
+            name: backup.metadata.name.clone(),
+            namespace: backup.metadata.namespace.clone(),
+            ..Default::default()
+        },
+        spec: ScheduledBackupSpec::builder()
+            .schedule(backup.spec.schedule.clone().unwrap_or_default())
+            .backup(spec.clone())
+            .maybe_concurrency_policy(backup.spec.concurrency_policy)
+            .maybe_suspend(backup.spec.suspend)
+            .maybe_time_zone(backup.spec.time_zone.clone())
+            .build(),
+        status: None,
+    }
+}
+
+/// Checks that Kubernetes will accept the CronJob schedule and time zone of the backup.
+pub fn validate(backup: &NodeBackup) -> Result<(), Error> {
+    match &backup.spec.schedule {
+        Some(_) => schedule::validate(&scheduled_backup(backup, &backup.spec.backup)),
+        None => Ok(()),
+    }
+}
+
+fn determine_action(backup: &NodeBackup) -> NodeBackupAction {
+    if backup.meta().deletion_timestamp.is_some() {
+        NodeBackupAction::Delete
+    } else if backup
+        .meta()
+        .finalizers
+        .as_ref()
+        .map_or(true, |f| !f.iter().any(|x| x == FINALIZER))
+    {
+        NodeBackupAction::Create
+    } else {
+        NodeBackupAction::Noop
+    }
+}
+
+fn on_error(backup: Arc<NodeBackup>, error: &Error, _context: Arc<ContextData>) -> Action {
+    error!("Reconciliation error:\n{:?}.\n{:?}", error, backup);
+    Action::requeue(Duration::from_secs(5))
+}
+
+/// Possible actions to take on a [`NodeBackup`] resource
+enum NodeBackupAction {
+    /// Create the sub-resources for the backup
+    Create,
+    /// Delete the sub-resources for the backup
+    Delete,
+    /// Deploy to new nodes and update the status.
+    Noop,
+}
+
+#[cfg(test)]
+mod tests {
+    use restic_crd::{Repository, RepositoryType, ResticConfig};
+
+    use super::*;
+
+    fn create_spec() -> NodeBackupSpec {
+        NodeBackupSpec::builder()
+            .paths(vec!["/etc".to_owned(), "/var/lib/rancher".to_owned()])
+            .backup(
+                BackupSpec::builder()
+                    .restic(
+                        ResticConfig::builder()
+                            .repository(
+                                Repository::builder()
+                                    .r#type(RepositoryType::Rest)
+                                    .uri("https://example.com".to_owned())
+                                    .password(Default::default())
+                                    .build(),
+                            )
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_node_spec() {
+        let spec = node_spec(&create_spec());
+        let vol_backup = spec.volume.unwrap();
+        assert_eq!(vol_backup.mounts[1].mount_path, "/host/var/lib/rancher");
+        assert_eq!(
+            vol_backup.volumes[1].host_path.as_ref().unwrap().path,
+            "/var/lib/rancher"
+        );
+        assert_eq!(vol_backup.path_prefix.as_deref(), Some(HOST_MOUNT_PATH));
+        assert_eq!(spec.restic_profile.unwrap().needs_root_access, Some(true));
+    }
+
+    #[test]
+    fn test_diff_nodes() {
+        let recorded = vec![
+            NodeStatus::builder().node_name("a".to_owned()).build(),
+            NodeStatus::builder().node_name("b".to_owned()).build(),
+        ];
+        let nodes = vec!["b".to_owned(), "c".to_owned()];
+        let (added, removed) = diff_nodes(&recorded, &nodes);
+        assert_eq!(added, vec!["c".to_owned()]);
+        assert_eq!(removed, vec!["a".to_owned()]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut backup = NodeBackup::new("test", create_spec());
+        assert!(validate(&backup).is_ok());
+
+        backup.spec.schedule = Some("0 2 * *".to_owned());
+        assert!(validate(&backup).is_err());
+    }
+}
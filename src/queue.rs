@@ -0,0 +1,283 @@
+use std::{collections::HashMap, time::Duration};
+
+use k8s_openapi::{api::batch::v1::Job, apimachinery::pkg::apis::meta::v1::Time};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use restic_crd::BackupPhase;
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::{config::Config, jobspec::job_phase, quiesce, Error};
+
+/// Annotation on a backup job that has to wait in the queue before starting.
+pub const QUEUED_ANNOTATION: &str = "restic.anshulg.com/queued";
+/// Annotation on a queued backup job once the queue has let it start.
+pub const ADMITTED_ANNOTATION: &str = "restic.anshulg.com/admitted";
+/// Annotation on a queued backup job storing its position in the queue.
+pub const POSITION_ANNOTATION: &str = "restic.anshulg.com/queue-position";
+/// Annotation on a backup job storing the repository it backs up to.
+pub const REPOSITORY_ANNOTATION: &str = "restic.anshulg.com/repository";
+/// Annotation on a backup job storing the maximum number of jobs running at once against its
+/// repository, overriding [`Config::max_concurrent_jobs_per_repository`].
+pub const MAX_CONCURRENT_JOBS_ANNOTATION: &str = "restic.anshulg.com/max-concurrent-jobs";
+
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts queued backup jobs in FIFO order as slots free up.
+pub async fn run(client: Client, config: Config) {
+    loop {
+        if let Err(err) = reconcile(client.clone(), &config).await {
+            error!(%err, "Queue reconciliation error");
+        }
+        tokio::time::sleep(INTERVAL).await;
+    }
+}
+
+async fn reconcile(client: Client, config: &Config) -> Result<(), Error> {
+    let api: Api<Job> = Api::all(client.clone());
+    let params = ListParams::default().labels("app.kubernetes.io/managed-by=restic-operator");
+    let jobs: Vec<Job> = api
+        .list(&params)
+        .await?
+        .into_iter()
+        .filter(|job| {
+            job.annotations().contains_key(QUEUED_ANNOTATION)
+                && !matches!(job_phase(job), BackupPhase::Completed | BackupPhase::Failed)
+        })
+        .collect();
+
+    let entries: Vec<_> = jobs.iter().map(Entry::from).collect();
+    for (job, decision) in jobs.iter().zip(plan(&entries, config)) {
+        let ns = job.namespace().unwrap_or_default();
+        let api: Api<Job> = Api::namespaced(client.clone(), &ns);
+
+        let patch = match decision {
+            Decision::Running => continue,
+            Decision::Admit => {
+                info!(job = job.name_any(), ns, "Admitting queued backup job");
+                let mut patch = json!({
+                    "metadata": {
+                        "annotations": { ADMITTED_ANNOTATION: "true", POSITION_ANNOTATION: null }
+                    }
+                });
+                // Quiesced jobs are resumed once their workload is scaled down
+                if !job.annotations().contains_key(quiesce::QUIESCE_ANNOTATION) {
+                    patch["spec"] = json!({ "suspend": false });
+                }
+                patch
+            }
+            Decision::Wait(position) => {
+                if queue_position(job) == Some(position) {
+                    continue;
+                }
+                json!({
+                    "metadata": {
+                        "annotations": { POSITION_ANNOTATION: position.to_string() }
+                    }
+                })
+            }
+        };
+
+        api.patch(
+            &job.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Whether the job may start, either because it is not queued or the queue has admitted it.
+pub fn is_admitted(job: &Job) -> bool {
+    let annotations = job.annotations();
+    !annotations.contains_key(QUEUED_ANNOTATION) || annotations.contains_key(ADMITTED_ANNOTATION)
+}
+
+/// Position of the job in the queue, or `None` if it is not waiting.
+pub fn queue_position(job: &Job) -> Option<u32> {
+    job.annotations()
+        .get(POSITION_ANNOTATION)
+        .and_then(|p| p.parse().ok())
+}
+
+/// A queued job that has not finished yet.
+#[derive(Debug, Clone)]
+struct Entry<'a> {
+    repository: &'a str,
+    /// Limit of running jobs of the repository set on the repository itself
+    max_per_repository: Option<usize>,
+    admitted: bool,
+    created: Option<&'a Time>,
+    name: &'a str,
+}
+
+impl<'a> From<&'a Job> for Entry<'a> {
+    fn from(job: &'a Job) -> Self {
+        Self {
+            repository: job
+                .annotations()
+                .get(REPOSITORY_ANNOTATION)
+                .map_or("", String::as_str),
+            max_per_repository: job
+                .annotations()
+                .get(MAX_CONCURRENT_JOBS_ANNOTATION)
+                .and_then(|m| m.parse().ok()),
+            admitted: is_admitted(job),
+            created: job.metadata.creation_timestamp.as_ref(),
+            name: job.metadata.name.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    /// Already admitted
+    Running,
+    /// Admit the job now
+    Admit,
+    /// Keep waiting at the given 1-based position
+    Wait(u32),
+}
+
+/// Decides which queued jobs to admit, in order of creation.
+///
+/// A job waiting on a full repository does not hold back jobs of other repositories. Each job
+/// is held to the limit of its own repository, falling back to the operator-wide one.
+fn plan(entries: &[Entry], config: &Config) -> Vec<Decision> {
+    let mut running = 0;
+    let mut running_per_repo: HashMap<&str, usize> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.admitted) {
+        running += 1;
+        *running_per_repo.entry(entry.repository).or_default() += 1;
+    }
+
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| (entries[i].created.map(|t| t.0), entries[i].name));
+
+    let mut decisions = vec![Decision::Running; entries.len()];
+    let mut position = 0;
+    for i in order.into_iter().filter(|&i| !entries[i].admitted) {
+        let repo_running = running_per_repo.entry(entries[i].repository).or_default();
+        let has_slot = config.max_concurrent_jobs.is_none_or(|max| running < max)
+            && entries[i]
+                .max_per_repository
+                .or(config.max_concurrent_jobs_per_repository)
+                .is_none_or(|max| *repo_running < max);
+
+        if has_slot {
+            running += 1;
+            *repo_running += 1;
+            decisions[i] = Decision::Admit;
+        } else {
+            position += 1;
+            decisions[i] = Decision::Wait(position);
+        }
+    }
+
+    decisions
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    fn times() -> Vec<Time> {
+        (0..5)
+            .map(|i| Time(Utc.timestamp_opt(1_700_000_000 + i, 0).unwrap()))
+            .collect()
+    }
+
+    fn entry<'a>(repository: &'a str, admitted: bool, created: &'a Time) -> Entry<'a> {
+        Entry {
+            repository,
+            max_per_repository: None,
+            admitted,
+            created: Some(created),
+            name: "job",
+        }
+    }
+
+    #[test]
+    fn test_plan_global_limit() {
+        let t = times();
+        let entries = vec![
+            entry("a", false, &t[2]),
+            entry("a", true, &t[0]),
+            entry("b", false, &t[1]),
+            entry("b", false, &t[3]),
+        ];
+        let config = Config {
+            max_concurrent_jobs: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan(&entries, &config),
+            vec![
+                Decision::Wait(1),
+                Decision::Running,
+                Decision::Admit,
+                Decision::Wait(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_repository_limit() {
+        let t = times();
+        let entries = vec![
+            entry("a", true, &t[0]),
+            entry("a", false, &t[1]),
+            entry("b", false, &t[2]),
+            entry("b", false, &t[3]),
+        ];
+        let config = Config {
+            max_concurrent_jobs_per_repository: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan(&entries, &config),
+            vec![
+                Decision::Running,
+                Decision::Wait(1),
+                Decision::Admit,
+                Decision::Wait(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_repository_override() {
+        let t = times();
+        let entries = vec![
+            entry("a", true, &t[0]),
+            Entry {
+                max_per_repository: Some(2),
+                ..entry("a", false, &t[1])
+            },
+            entry("b", true, &t[2]),
+            entry("b", false, &t[3]),
+        ];
+        let config = Config {
+            max_concurrent_jobs_per_repository: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan(&entries, &config),
+            vec![
+                Decision::Running,
+                Decision::Admit,
+                Decision::Running,
+                Decision::Wait(1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, StatefulSet},
+        batch::v1::Job,
+        core::v1::Pod,
+    },
+    chrono::{DateTime, Utc},
+};
+use kube::{
+    api::{ApiResource, DeleteParams, DynamicObject, ListParams, Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use restic_crd::{BackupPhase, Quiesce, WorkloadKind};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{jobspec::job_phase, queue, Error};
+
+/// Annotation on a backup job naming the workload to scale down before it starts.
+pub const QUIESCE_ANNOTATION: &str = "restic.anshulg.com/quiesce";
+/// Annotation on the workload storing its replica count before it was scaled down.
+pub const REPLICAS_ANNOTATION: &str = "restic.anshulg.com/quiesce-replicas";
+/// Annotation on the workload storing the name of the job it was scaled down for.
+pub const JOB_ANNOTATION: &str = "restic.anshulg.com/quiesce-job";
+/// Annotation on the workload storing when it was scaled down.
+pub const TIME_ANNOTATION: &str = "restic.anshulg.com/quiesce-time";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Advances the quiesce of a workload for the given backup jobs.
+///
+/// Backup jobs are created suspended. The oldest waiting job scales the workload down to zero,
+/// and is resumed once all of the workload's pods are gone. When the job finishes, whether it
+/// succeeded or not, the workload is scaled back to its original replica count.
+///
+/// Returns the job if the quiesce timed out before it started. It must be passed to [`abandon`]
+/// once its failure is recorded, as its phase is lost when it is deleted.
+pub async fn reconcile(
+    client: Client,
+    ns: &str,
+    quiesce: &Quiesce,
+    jobs: &[Job],
+) -> Result<Option<Job>, Error> {
+    let api = workload_api(client.clone(), ns, quiesce.kind);
+    let workload = api.get(&quiesce.name).await?;
+    let job_api: Api<Job> = Api::namespaced(client.clone(), ns);
+
+    match workload.annotations().get(JOB_ANNOTATION) {
+        Some(holder) => {
+            if let Some(job) = jobs.iter().find(|j| j.name_any() == *holder) {
+                let pod_api: Api<Pod> = Api::namespaced(client, ns);
+                advance(&api, &job_api, &pod_api, quiesce, &workload, job).await
+            } else if job_api.get_opt(holder).await?.is_none() {
+                warn!(
+                    workload = quiesce.name,
+                    job = holder,
+                    "Quiesce job no longer exists, restoring workload"
+                );
+                restore(&api, &workload).await?;
+                Ok(None)
+            } else {
+                // Held by the job of another backup
+                Ok(None)
+            }
+        }
+        None => {
+            let waiting = jobs
+                .iter()
+                .filter(|j| is_waiting(j))
+                .min_by_key(|j| j.creation_timestamp());
+            if let Some(job) = waiting {
+                scale_down(&api, &workload, job).await?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Deletes a job whose quiesce timed out before it started, and restores the workload.
+///
+/// Resuming the job would start a backup pod, so it never runs instead.
+pub async fn abandon(client: Client, ns: &str, quiesce: &Quiesce, job: &Job) -> Result<(), Error> {
+    let job_api: Api<Job> = Api::namespaced(client.clone(), ns);
+    match job_api
+        .delete(&job.name_any(), &DeleteParams::background())
+        .await
+    {
+        Ok(_) => {}
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+        Err(e) => return Err(Error::KubeError(e)),
+    }
+    release(client, ns, quiesce, &[job.name_any()]).await
+}
+
+/// Restores the workload if it was scaled down for one of the given jobs.
+pub async fn release(
+    client: Client,
+    ns: &str,
+    quiesce: &Quiesce,
+    jobs: &[String],
+) -> Result<(), Error> {
+    let api = workload_api(client, ns, quiesce.kind);
+    let Some(workload) = api.get_opt(&quiesce.name).await? else {
+        return Ok(());
+    };
+
+    if workload
+        .annotations()
+        .get(JOB_ANNOTATION)
+        .is_some_and(|holder| jobs.contains(holder))
+    {
+        restore(&api, &workload).await?;
+    }
+    Ok(())
+}
+
+async fn advance(
+    api: &Api<DynamicObject>,
+    job_api: &Api<Job>,
+    pod_api: &Api<Pod>,
+    quiesce: &Quiesce,
+    workload: &DynamicObject,
+    job: &Job,
+) -> Result<Option<Job>, Error> {
+    let timeout = quiesce
+        .timeout_seconds
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+
+    if matches!(job_phase(job), BackupPhase::Completed | BackupPhase::Failed) {
+        restore(api, workload).await?;
+        Ok(None)
+    } else if quiesced_for(workload).is_some_and(|d| d > timeout) {
+        if is_suspended(job) {
+            warn!(
+                workload = quiesce.name,
+                job = job.name_any(),
+                "Quiesce timed out before the backup job started"
+            );
+            return Ok(Some(job.clone()));
+        }
+
+        warn!(
+            workload = quiesce.name,
+            job = job.name_any(),
+            "Quiesce timed out, failing backup job"
+        );
+        // A short deadline makes the job controller fail the running job
+        let patch = json!({ "spec": { "activeDeadlineSeconds": 1 } });
+        job_api
+            .patch(
+                &job.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            )
+            .await?;
+        restore(api, workload).await?;
+        Ok(None)
+    } else if is_suspended(job) && pod_count(pod_api, workload).await? == 0 {
+        info!(
+            workload = quiesce.name,
+            job = job.name_any(),
+            "Workload quiesced, starting backup job"
+        );
+        let patch = json!({ "spec": { "suspend": false } });
+        job_api
+            .patch(
+                &job.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            )
+            .await?;
+        Ok(None)
+    } else {
+        Ok(None)
+    }
+}
+
+async fn scale_down(
+    api: &Api<DynamicObject>,
+    workload: &DynamicObject,
+    job: &Job,
+) -> Result<(), Error> {
+    let replicas = workload.data["spec"]["replicas"].as_i64().unwrap_or(1);
+    info!(
+        workload = workload.name_any(),
+        job = job.name_any(),
+        replicas,
+        "Scaling down workload for backup"
+    );
+
+    let patch = json!({
+        "metadata": {
+            "annotations": {
+                REPLICAS_ANNOTATION: replicas.to_string(),
+                JOB_ANNOTATION: job.name_any(),
+                TIME_ANNOTATION: Utc::now().to_rfc3339(),
+            }
+        },
+        "spec": { "replicas": 0 }
+    });
+    api.patch(
+        &workload.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn restore(api: &Api<DynamicObject>, workload: &DynamicObject) -> Result<(), Error> {
+    let replicas = workload
+        .annotations()
+        .get(REPLICAS_ANNOTATION)
+        .and_then(|r| r.parse::<i64>().ok());
+    info!(
+        workload = workload.name_any(),
+        replicas, "Restoring quiesced workload"
+    );
+
+    let mut patch = json!({
+        "metadata": {
+            "annotations": {
+                REPLICAS_ANNOTATION: null,
+                JOB_ANNOTATION: null,
+                TIME_ANNOTATION: null,
+            }
+        }
+    });
+    if let Some(replicas) = replicas {
+        patch["spec"] = json!({ "replicas": replicas });
+    }
+    api.patch(
+        &workload.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    Ok(())
+}
+
+fn workload_api(client: Client, ns: &str, kind: WorkloadKind) -> Api<DynamicObject> {
+    let resource = match kind {
+        WorkloadKind::Deployment => ApiResource::erase::<Deployment>(&()),
+        WorkloadKind::StatefulSet => ApiResource::erase::<StatefulSet>(&()),
+    };
+    Api::namespaced_with(client, ns, &resource)
+}
+
+/// Whether the job is suspended, so it has not started a pod yet.
+fn is_suspended(job: &Job) -> bool {
+    job.spec.as_ref().and_then(|s| s.suspend) == Some(true)
+}
+
+/// Whether the job is suspended and waiting for the workload to be scaled down.
+fn is_waiting(job: &Job) -> bool {
+    is_suspended(job)
+        && queue::is_admitted(job)
+        && !matches!(job_phase(job), BackupPhase::Completed | BackupPhase::Failed)
+}
+
+/// Number of pods the workload currently has, including terminating ones.
+async fn pod_count(pod_api: &Api<Pod>, workload: &DynamicObject) -> Result<usize, Error> {
+    match selector(workload) {
+        Some(selector) => {
+            let pods = pod_api
+                .list_metadata(&ListParams::default().labels(&selector))
+                .await?;
+            Ok(pods.items.len())
+        }
+        // Terminating pods are not counted without a selector
+        None => Ok(workload.data["status"]["replicas"]
+            .as_u64()
+            .unwrap_or_default() as usize),
+    }
+}
+
+/// Label selector of the workload's pods, from `spec.selector.matchLabels`.
+fn selector(workload: &DynamicObject) -> Option<String> {
+    let labels = workload.data["spec"]["selector"]["matchLabels"].as_object()?;
+    let selector: Vec<_> = labels
+        .iter()
+        .filter_map(|(k, v)| Some(format!("{k}={}", v.as_str()?)))
+        .collect();
+    (!selector.is_empty()).then(|| selector.join(","))
+}
+
+/// How long the workload has been scaled down for.
+fn quiesced_for(workload: &DynamicObject) -> Option<Duration> {
+    let time = workload.annotations().get(TIME_ANNOTATION)?;
+    let time = DateTime::parse_from_rfc3339(time).ok()?;
+    (Utc::now() - time.with_timezone(&Utc)).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::batch::v1::JobSpec;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn workload(data: Value, annotations: &[(&str, &str)]) -> DynamicObject {
+        let mut obj = DynamicObject::new("app", &ApiResource::erase::<Deployment>(&())).data(data);
+        obj.metadata.annotations = Some(
+            annotations
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+                .collect(),
+        );
+        obj
+    }
+
+    #[test]
+    fn test_is_waiting() {
+        let mut job = Job {
+            spec: Some(JobSpec {
+                suspend: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(is_waiting(&job));
+
+        job.metadata.annotations = Some(BTreeMap::from([(
+            queue::QUEUED_ANNOTATION.to_owned(),
+            "true".to_owned(),
+        )]));
+        assert!(!is_waiting(&job));
+
+        job.spec.as_mut().unwrap().suspend = Some(false);
+        assert!(!is_waiting(&job));
+    }
+
+    #[test]
+    fn test_selector() {
+        let obj = workload(
+            json!({ "spec": { "selector": { "matchLabels": { "app": "gitea", "tier": "web" } } } }),
+            &[],
+        );
+        assert_eq!(selector(&obj), Some("app=gitea,tier=web".to_owned()));
+
+        let obj = workload(json!({ "spec": {} }), &[]);
+        assert_eq!(selector(&obj), None);
+    }
+
+    #[test]
+    fn test_quiesced_for() {
+        let obj = workload(json!({}), &[]);
+        assert_eq!(quiesced_for(&obj), None);
+
+        let time = (Utc::now() - k8s_openapi::chrono::Duration::minutes(5)).to_rfc3339();
+        let obj = workload(json!({}), &[(TIME_ANNOTATION, &time)]);
+        assert!(quiesced_for(&obj).unwrap() >= Duration::from_secs(300));
+    }
+}
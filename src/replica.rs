@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+use k8s_openapi::api::{
+    apps::v1::{StatefulSet, StatefulSetSpec},
+    core::v1::{PersistentVolumeClaimVolumeSource, Volume, VolumeMount},
+};
+use kube::{Api, Client};
+use restic_crd::{BackupSpec, ReplicaStatus, StatefulSetBackup, VolumeBackup};
+
+use crate::Error;
+
+const DEFAULT_MOUNT_PATH: &str = "/data";
+
+/// A single replica of a StatefulSet targeted by a backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replica {
+    pub ordinal: i32,
+    pub claim_name: String,
+}
+
+impl Replica {
+    pub fn new(sts: &StatefulSetBackup, ordinal: i32) -> Self {
+        Self {
+            ordinal,
+            claim_name: format!("{}-{}-{}", sts.volume_claim_template, sts.name, ordinal),
+        }
+    }
+}
+
+impl From<&ReplicaStatus> for Replica {
+    fn from(value: &ReplicaStatus) -> Self {
+        Self {
+            ordinal: value.ordinal,
+            claim_name: value.claim_name.clone(),
+        }
+    }
+}
+
+/// Lists the replicas of the StatefulSet targeted by the backup.
+///
+/// Returns an empty list if the backup does not target a StatefulSet.
+pub async fn list(client: Client, ns: &str, backup: &BackupSpec) -> Result<Vec<Replica>, Error> {
+    let Some(sts) = &backup.stateful_set else {
+        return Ok(Vec::new());
+    };
+
+    let api: Api<StatefulSet> = Api::namespaced(client, ns);
+    let stateful_set = api.get(&sts.name).await?;
+
+    Ok(ordinals(&stateful_set.spec.unwrap_or_default())
+        .map(|i| Replica::new(sts, i))
+        .collect())
+}
+
+/// Ordinals of the replicas of a StatefulSet, starting at `ordinals.start`.
+fn ordinals(spec: &StatefulSetSpec) -> Range<i32> {
+    let replicas = spec.replicas.unwrap_or(1).max(0);
+    let start = spec
+        .ordinals
+        .as_ref()
+        .and_then(|o| o.start)
+        .unwrap_or(0)
+        .max(0);
+    start..start + replicas
+}
+
+/// A single backup run, with the name used for its sub-resources and restic host.
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub name: String,
+    pub spec: BackupSpec,
+    pub replica: Option<Replica>,
+}
+
+/// Splits a backup into the runs that need to be deployed.
+///
+/// A backup that does not target a StatefulSet is a single run named after the resource.
+/// Otherwise, each replica gets its own run named `{name}-{ordinal}` that mounts the replica's
+/// PersistentVolumeClaim.
+pub fn runs(name: &str, backup: &BackupSpec, replicas: &[Replica]) -> Vec<Run> {
+    let Some(sts) = &backup.stateful_set else {
+        return vec![Run {
+            name: name.to_owned(),
+            spec: backup.clone(),
+            replica: None,
+        }];
+    };
+
+    replicas
+        .iter()
+        .map(|replica| Run {
+            name: format!("{name}-{}", replica.ordinal),
+            spec: replica_spec(backup, sts, replica),
+            replica: Some(replica.clone()),
+        })
+        .collect()
+}
+
+fn replica_spec(backup: &BackupSpec, sts: &StatefulSetBackup, replica: &Replica) -> BackupSpec {
+    let mut spec = backup.clone();
+    // Manifests are shared by all replicas, so only back them up once
+    if replica.ordinal != 0 {
+        spec.resources = None;
+    }
+
+    let vol_backup = spec.volume.get_or_insert_with(|| VolumeBackup {
+        mounts: Vec::new(),
+        volumes: Vec::new(),
+        read_only: None,
+        tags: None,
+        path_prefix: None,
+    });
+
+    vol_backup.mounts.push(VolumeMount {
+        mount_path: sts
+            .mount_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MOUNT_PATH.to_owned()),
+        name: sts.volume_claim_template.clone(),
+        ..Default::default()
+    });
+    vol_backup.volumes.push(Volume {
+        name: sts.volume_claim_template.clone(),
+        persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+            claim_name: replica.claim_name.clone(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::apps::v1::StatefulSetOrdinals;
+    use restic_crd::{Repository, RepositoryType, ResticConfig};
+
+    use super::*;
+
+    fn create_backup(sts: Option<StatefulSetBackup>) -> BackupSpec {
+        BackupSpec::builder()
+            .restic(
+                ResticConfig::builder()
+                    .repository(
+                        Repository::builder()
+                            .r#type(RepositoryType::Rest)
+                            .uri("https://example.com".to_owned())
+                            .password(Default::default())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .maybe_stateful_set(sts)
+            .build()
+    }
+
+    #[test]
+    fn test_runs_without_stateful_set() {
+        let backup = create_backup(None);
+        let runs = runs("test", &backup, &[]);
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].name, "test");
+        assert_eq!(runs[0].replica, None);
+    }
+
+    #[test]
+    fn test_runs_with_stateful_set() {
+        let sts = StatefulSetBackup::builder()
+            .name("foo".to_owned())
+            .volume_claim_template("data".to_owned())
+            .build();
+        let backup = create_backup(Some(sts.clone()));
+        let replicas = vec![Replica::new(&sts, 0), Replica::new(&sts, 1)];
+        let runs = runs("test", &backup, &replicas);
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].name, "test-1");
+
+        let vol_backup = runs[1].spec.volume.as_ref().unwrap();
+        assert_eq!(vol_backup.mounts[0].mount_path, DEFAULT_MOUNT_PATH);
+        assert_eq!(
+            vol_backup.volumes[0]
+                .persistent_volume_claim
+                .as_ref()
+                .unwrap()
+                .claim_name,
+            "data-foo-1"
+        );
+    }
+
+    #[test]
+    fn test_ordinals() {
+        let spec = StatefulSetSpec {
+            replicas: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(ordinals(&spec), 0..3);
+
+        let spec = StatefulSetSpec {
+            replicas: Some(2),
+            ordinals: Some(StatefulSetOrdinals { start: Some(5) }),
+            ..Default::default()
+        };
+        assert_eq!(ordinals(&spec), 5..7);
+        assert_eq!(ordinals(&StatefulSetSpec::default()), 0..1);
+    }
+}
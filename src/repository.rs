@@ -0,0 +1,141 @@
+//! Repositories given by reference to a Secret in `v1beta1`, which `v1alpha1` objects carry in
+//! the [`REPOSITORY_REF_ANNOTATION`] annotation.
+
+use k8s_openapi::api::core::v1::{Secret, SecretKeySelector};
+use kube::{api::ObjectMeta, Api, Client};
+use restic_crd::{
+    v1beta1::{
+        REPOSITORY_PASSWORD_KEY, REPOSITORY_REF_ANNOTATION, REPOSITORY_URI_KEY, REST_PASSWORD_KEY,
+        REST_USERNAME_KEY,
+    },
+    BackupSpec, Repository, RepositoryType, RestCredentials,
+};
+
+use crate::Error;
+
+/// Replaces the repository of a backup given by reference with the one in its Secret.
+///
+/// Backups with an inline repository are left as is.
+pub async fn resolve(
+    client: Client,
+    metadata: &ObjectMeta,
+    spec: &mut BackupSpec,
+) -> Result<(), Error> {
+    let Some(name) = metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(REPOSITORY_REF_ANNOTATION))
+    else {
+        return Ok(());
+    };
+    let ns = metadata
+        .namespace
+        .as_deref()
+        .ok_or(Error::MissingNamespace)?;
+    let secret = Api::<Secret>::namespaced(client, ns).get(name).await?;
+    spec.restic.repository = from_secret(name, &secret)?;
+    Ok(())
+}
+
+/// Reads a repository from the keys of its Secret.
+fn from_secret(name: &str, secret: &Secret) -> Result<Repository, Error> {
+    let data = secret.data.clone().unwrap_or_default();
+    let missing = |key: &str| Error::MissingSecretKey {
+        name: name.to_owned(),
+        key: key.to_owned(),
+    };
+    let selector = |key: &str| SecretKeySelector {
+        name: name.to_owned(),
+        key: key.to_owned(),
+        optional: None,
+    };
+
+    let uri = data
+        .get(REPOSITORY_URI_KEY)
+        .ok_or_else(|| missing(REPOSITORY_URI_KEY))?;
+    if !data.contains_key(REPOSITORY_PASSWORD_KEY) {
+        return Err(missing(REPOSITORY_PASSWORD_KEY));
+    }
+    let rest_credentials = match (
+        data.contains_key(REST_USERNAME_KEY),
+        data.contains_key(REST_PASSWORD_KEY),
+    ) {
+        (false, false) => None,
+        (true, false) => return Err(missing(REST_PASSWORD_KEY)),
+        (false, true) => return Err(missing(REST_USERNAME_KEY)),
+        (true, true) => Some(RestCredentials {
+            username: selector(REST_USERNAME_KEY),
+            password: selector(REST_PASSWORD_KEY),
+        }),
+    };
+
+    Ok(Repository {
+        r#type: RepositoryType::Rest,
+        uri: String::from_utf8_lossy(&uri.0).trim().to_owned(),
+        password: selector(REPOSITORY_PASSWORD_KEY),
+        rest_credentials,
+        version: None,
+        max_concurrent_jobs: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::ByteString;
+
+    use super::*;
+
+    fn secret(keys: &[(&str, &str)]) -> Secret {
+        Secret {
+            data: Some(
+                keys.iter()
+                    .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_secret() {
+        let repo = from_secret(
+            "offsite",
+            &secret(&[("uri", "https://example.com\n"), ("password", "hunter2")]),
+        )
+        .unwrap();
+        assert_eq!(repo.full_uri(), "rest:https://example.com");
+        assert_eq!(repo.password.name, "offsite");
+        assert_eq!(repo.password.key, REPOSITORY_PASSWORD_KEY);
+        assert!(repo.rest_credentials.is_none());
+
+        let repo = from_secret(
+            "offsite",
+            &secret(&[
+                ("uri", "https://example.com"),
+                ("password", "hunter2"),
+                ("restUsername", "user"),
+                ("restPassword", "pass"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            repo.rest_credentials.unwrap().username.key,
+            REST_USERNAME_KEY
+        );
+
+        let err = from_secret("offsite", &secret(&[("password", "hunter2")])).unwrap_err();
+        assert!(matches!(err, Error::MissingSecretKey { key, .. } if key == REPOSITORY_URI_KEY));
+        let err = from_secret(
+            "offsite",
+            &secret(&[
+                ("uri", "https://example.com"),
+                ("password", "hunter2"),
+                ("restUsername", "user"),
+            ]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::MissingSecretKey { key, .. } if key == REST_PASSWORD_KEY));
+    }
+}
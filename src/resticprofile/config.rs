@@ -4,6 +4,9 @@ use bon::Builder;
 use serde::{Deserialize, Serialize};
 
 pub const DEFAULT_PROFILE: &str = "default";
+pub const MANIFESTS_PROFILE: &str = "manifests";
+/// Group running every profile of the config.
+pub const DEFAULT_GROUP: &str = "all";
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Builder, Default)]
 #[non_exhaustive]
@@ -11,6 +14,10 @@ pub struct ResticProfileConfig {
     #[builder(default)]
     pub version: ResticProfileVersion,
     pub global: Option<ResticProfileGlobal>,
+    /// Groups of profiles that can be run together.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[builder(default)]
+    pub groups: HashMap<String, Vec<String>>,
     #[serde(flatten)]
     #[builder(default)]
     pub profiles: HashMap<String, ResticProfileProfile>,
@@ -40,6 +47,8 @@ pub struct ResticProfileGlobal {
 #[non_exhaustive]
 #[serde(rename_all = "kebab-case")]
 pub struct ResticProfileProfile {
+    /// Sets the working directory for this profile. The profile will fail when the working directory cannot be set.
+    pub base_dir: Option<String>,
     /// File to load root certificates from (default: use system certificates or $RESTIC_CACERT).
     pub cacert: Option<String>,
     /// Set the cache directory. (default: use system default cache directory).
@@ -79,6 +88,8 @@ pub struct ResticProfileProfile {
     pub backup: Option<ResticProfileProfileBackup>,
     /// This section configures restic command `forget`.
     pub retention: Option<ResticProfileProfileRetention>,
+    /// This section configures restic command `copy`.
+    pub copy: Option<ResticProfileProfileCopy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Builder, Default)]
@@ -121,6 +132,58 @@ pub struct ResticProfileProfileBackup {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[builder(default)]
     pub iexclude: Vec<String>,
+    /// Same as –exclude pattern but reads the patterns from a file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub exclude_file: Vec<String>,
+    /// Same as –iexclude pattern but reads the patterns from a file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub iexclude_file: Vec<String>,
+    /// Read the files to backup from file (can be combined with source).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub files_from: Vec<String>,
+    /// Ignore ctime changes when checking for modified files.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub ignore_ctime: bool,
+    /// Ignore inode number and ctime changes when checking for modified files.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub ignore_inode: bool,
+    /// Skip snapshot creation if identical to parent snapshot.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub skip_if_unchanged: bool,
+    /// Exclude other file systems, don’t cross filesystem boundaries and subvolumes.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub one_file_system: bool,
+    /// Read n files concurrently.
+    pub read_concurrency: Option<u32>,
+    /// Do not run scanner to estimate size of backup.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub no_scan: bool,
+    /// Store the atime for all files and directories.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub with_atime: bool,
+    /// Excludes online-only cloud files (such as OneDrive Files On-Demand).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub exclude_cloud_files: bool,
+    /// Do not upload or write any data, just show what would be done.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub dry_run: bool,
+    /// Shell command(s) that generate content to redirect into the stdin of restic. When set, the flag `stdin` is always set to true.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub stdin_command: Vec<String>,
+    /// Filename for backup from stdin.
+    pub stdin_filename: Option<String>,
     /// Add tags for the new snapshot in the format tag[,tag,…]. Boolean true is unsupported in section “backup”. Examples: false, "tag".
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[builder(default)]
@@ -139,8 +202,9 @@ pub struct ResticProfileProfileRetention {
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     #[builder(default)]
     pub before_backup: bool,
-    #[builder(default)]
-    pub host: bool,
+    /// Only forget snapshots of the given host, or of the current hostname if `true`.
+    #[builder(default, into)]
+    pub host: ResticProfileHost,
 
     pub keep_last: Option<u32>,
     pub keep_hourly: Option<u32>,
@@ -148,6 +212,20 @@ pub struct ResticProfileProfileRetention {
     pub keep_weekly: Option<u32>,
     pub keep_monthly: Option<u32>,
     pub keep_yearly: Option<u32>,
+    pub keep_within: Option<String>,
+    pub keep_within_hourly: Option<String>,
+    pub keep_within_daily: Option<String>,
+    pub keep_within_weekly: Option<String>,
+    pub keep_within_monthly: Option<String>,
+    pub keep_within_yearly: Option<String>,
+    pub keep_tag: Option<Vec<String>>,
+
+    /// Group snapshots by host, paths and/or tags.
+    pub group_by: Option<String>,
+    /// Only forget snapshots with these tags.
+    pub tag: Option<Vec<String>>,
+    /// Only forget snapshots of these paths.
+    pub path: Option<Vec<String>>,
 
     /// Automatically run the ‘prune’ command if snapshots have been removed.
     #[serde(skip_serializing_if = "std::ops::Not::not")]
@@ -155,6 +233,48 @@ pub struct ResticProfileProfileRetention {
     pub prune: bool,
 }
 
+/// Host filter of a command, either the current hostname if `true`, or a given host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResticProfileHost {
+    Current(bool),
+    Name(String),
+}
+
+impl Default for ResticProfileHost {
+    fn default() -> Self {
+        Self::Current(false)
+    }
+}
+
+impl From<bool> for ResticProfileHost {
+    fn from(value: bool) -> Self {
+        Self::Current(value)
+    }
+}
+
+impl From<String> for ResticProfileHost {
+    fn from(value: String) -> Self {
+        Self::Name(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Builder, Default)]
+#[non_exhaustive]
+#[serde(rename_all = "kebab-case")]
+pub struct ResticProfileProfileCopy {
+    /// Destination repository to copy snapshots to.
+    pub repository: String,
+    /// File to read the destination repository password from.
+    pub password_file: Option<String>,
+    /// Only copy snapshots for this host.
+    pub host: Option<String>,
+    /// Only copy snapshots that include this tag.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub tag: Vec<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -196,6 +316,26 @@ restic-stale-lock-age = "1h"
         );
     }
 
+    #[test]
+    fn test_groups() {
+        let config = ResticProfileConfig::builder()
+            .groups(HashMap::from([(
+                DEFAULT_GROUP.to_owned(),
+                vec![DEFAULT_PROFILE.to_owned(), MANIFESTS_PROFILE.to_owned()],
+            )]))
+            .build();
+
+        let output = toml::to_string(&config).unwrap();
+        assert_eq!(
+            output,
+            r#"version = "1"
+
+[groups]
+all = ["default", "manifests"]
+"#
+        );
+    }
+
     #[test]
     fn test_profile() {
         let config = ResticProfileConfig::builder()
@@ -240,6 +380,69 @@ source = ["/opt"]
 after-backup = true
 host = false
 keep-last = 10
+"#
+        );
+    }
+
+    #[test]
+    fn test_backup_flags() {
+        let backup = ResticProfileProfileBackup::builder()
+            .source(vec!["/opt".to_owned()])
+            .skip_if_unchanged(true)
+            .read_concurrency(4)
+            .build();
+
+        let output = toml::to_string(&backup).unwrap();
+        assert_eq!(
+            output,
+            r#"source = ["/opt"]
+skip-if-unchanged = true
+read-concurrency = 4
+"#
+        );
+    }
+
+    #[test]
+    fn test_retention() {
+        let retention = ResticProfileProfileRetention::builder()
+            .after_backup(true)
+            .host("node".to_owned())
+            .keep_last(10)
+            .keep_within_daily("7d".to_owned())
+            .keep_tag(vec!["keep".to_owned()])
+            .group_by("host,tags".to_owned())
+            .build();
+
+        let output = toml::to_string(&retention).unwrap();
+        assert_eq!(
+            output,
+            r#"after-backup = true
+host = "node"
+keep-last = 10
+keep-within-daily = "7d"
+keep-tag = ["keep"]
+group-by = "host,tags"
+"#
+        );
+
+        let retention = ResticProfileProfileRetention::builder().host(true).build();
+        assert_eq!(toml::to_string(&retention).unwrap(), "host = true\n");
+    }
+
+    #[test]
+    fn test_copy() {
+        let copy = ResticProfileProfileCopy::builder()
+            .repository("rest:https://offsite.example.com".to_owned())
+            .password_file("/offsite/password.txt".to_owned())
+            .host("test".to_owned())
+            .build();
+
+        let output = toml::to_string(&copy).unwrap();
+        assert_eq!(
+            output,
+            r#"repository = "rest:https://offsite.example.com"
+password-file = "/offsite/password.txt"
+host = "test"
 "#
         );
     }
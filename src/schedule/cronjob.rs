@@ -1,17 +1,27 @@
 use k8s_openapi::{
-    api::batch::v1::{CronJob, CronJobSpec, JobTemplateSpec},
+    api::batch::v1::{CronJob, CronJobSpec, Job, JobTemplateSpec},
     apimachinery::pkg::apis::meta::v1::OwnerReference,
 };
-use kube::{api::ObjectMeta, Api, Resource, ResourceExt};
-use restic_crd::ScheduledBackup;
+use kube::{
+    api::{ListParams, ObjectMeta},
+    Api, Resource, ResourceExt,
+};
+use restic_crd::{BackupSpec, ConcurrencyPolicy, ScheduledBackup};
 
-use super::Error;
-use crate::{deploy::Deployable, jobspec::BackupJobSpec};
+use super::{hashed, Error};
+use crate::{
+    deploy::{Deployable, Labels},
+    hash,
+    jobspec::BackupJobSpec,
+    manifests,
+};
 
 #[derive(Debug, Clone)]
 pub struct BackupCronJob {
     name: String,
     ns: String,
+    /// Name of the backup, which the jobs are labelled with
+    app_name: String,
     spec: BackupJobSpec,
 
     /// The schedule in Cron format, see https://en.wikipedia.org/wiki/Cron.
@@ -19,7 +29,7 @@ pub struct BackupCronJob {
     /// Specifies how to treat concurrent executions of a Job. Valid values are:
     ///
     /// - "Allow" (default): allows CronJobs to run concurrently; - "Forbid": forbids concurrent runs, skipping next run if previous run hasn't finished yet; - "Replace": cancels currently running job and replaces it with a new one
-    pub concurrency_policy: Option<String>,
+    pub concurrency_policy: Option<ConcurrencyPolicy>,
     /// The number of failed finished jobs to retain. Value must be non-negative integer. Defaults to 1.
     pub failed_jobs_history_limit: Option<i32>,
     /// Optional deadline in seconds for starting the job if it misses scheduled time for any reason.  Missed jobs executions will be counted as failed ones.
@@ -35,16 +45,23 @@ pub struct BackupCronJob {
 impl BackupCronJob {
     pub fn new(
         ns: impl Into<String>,
+        name: impl Into<String>,
         backup: &ScheduledBackup,
+        spec: &BackupSpec,
         config_name: impl Into<String>,
     ) -> Self {
-        let spec = BackupJobSpec::new(&backup.spec.backup, config_name);
+        let name = name.into();
+        let mut job_spec = BackupJobSpec::new(spec, config_name);
+        if spec.resources.is_some() {
+            job_spec = job_spec.with_manifests(manifests::secret_name(&name));
+        }
         Self {
-            name: format!("{}-cronjob", backup.name_any()),
+            name: format!("{name}-cronjob"),
             ns: ns.into(),
-            spec,
-            schedule: backup.spec.schedule.clone(),
-            concurrency_policy: backup.spec.concurrency_policy.clone(),
+            app_name: backup.name_any(),
+            spec: job_spec,
+            schedule: schedule(backup),
+            concurrency_policy: backup.spec.concurrency_policy,
             failed_jobs_history_limit: backup.spec.failed_jobs_history_limit,
             starting_deadline_seconds: backup.spec.starting_deadline_seconds,
             successful_jobs_history_limit: backup.spec.successful_jobs_history_limit,
@@ -53,6 +70,88 @@ impl BackupCronJob {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs the backup pods on the given node.
+    pub fn with_node_name(mut self, node_name: impl Into<String>) -> Self {
+        self.spec = self.spec.with_node_name(node_name);
+        self
+    }
+
+    /// Holds the cronjob's jobs until the queue admits them.
+    pub fn with_queue(mut self) -> Self {
+        self.spec = self.spec.with_queue();
+        self
+    }
+
+    /// Lists the jobs spawned by the cronjob.
+    pub async fn jobs(&self, client: kube::Client) -> Result<Vec<Job>, Error> {
+        let api: Api<Job> = Api::namespaced(client, &self.ns);
+        let selector = Labels::new(self.app_name.clone()).selector();
+        let jobs = api.list(&ListParams::default().labels(&selector)).await?;
+        Ok(jobs
+            .into_iter()
+            .filter(|job| {
+                job.owner_references()
+                    .iter()
+                    .any(|o| o.kind == "CronJob" && o.name == self.name)
+            })
+            .collect())
+    }
+
+    /// Gets the most recently created job of the cronjob, if any.
+    pub async fn last_job(&self, client: kube::Client) -> Result<Option<Job>, Error> {
+        Ok(self
+            .jobs(client)
+            .await?
+            .into_iter()
+            .max_by(|a, b| a.creation_timestamp().cmp(&b.creation_timestamp())))
+    }
+
+    /// Creates a one-off job from the cronjob's job template, like
+    /// `kubectl create job --from=cronjob/...`.
+    ///
+    /// The job name is derived from `id`, so creating a job twice for the same `id` is a no-op.
+    pub async fn run_now(&self, client: kube::Client, id: &str) -> Result<String, Error> {
+        let api: Api<CronJob> = Api::namespaced(client.clone(), &self.ns);
+        let cron_job = api.get(&self.name).await?;
+        let template = cron_job
+            .spec
+            .as_ref()
+            .map(|s| s.job_template.clone())
+            .unwrap_or_default();
+        let template_meta = template.metadata.unwrap_or_default();
+
+        let name = format!("{}-{:08x}", self.name, hash::fnv1a(id.as_bytes()));
+        let mut annotations = template_meta.annotations.unwrap_or_default();
+        annotations.insert(
+            "cronjob.kubernetes.io/instantiate".to_owned(),
+            "manual".to_owned(),
+        );
+
+        let job = Job {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(self.ns.clone()),
+                labels: template_meta.labels,
+                annotations: Some(annotations),
+                owner_references: cron_job.controller_owner_ref(&()).map(|o| vec![o]),
+                ..Default::default()
+            },
+            spec: template.spec,
+            ..Default::default()
+        };
+
+        let api: Api<Job> = Api::namespaced(client, &self.ns);
+        match api.create(&Default::default(), &job).await {
+            Ok(_) => Ok(name),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(name),
+            Err(e) => Err(Error::KubeError(e)),
+        }
+    }
+
     async fn get(&self, client: kube::Client) -> Result<Option<CronJob>, Error> {
         let api: Api<CronJob> = Api::namespaced(client, &self.ns);
         match api.get(&self.name).await {
@@ -69,6 +168,19 @@ impl BackupCronJob {
     }
 }
 
+/// Schedule of the backup's cronjobs, with `H` tokens resolved from the backup's namespace and
+/// name.
+///
+/// Every run of a backup uses the same seed, so they are all scheduled at the same time.
+pub fn schedule(backup: &ScheduledBackup) -> String {
+    let seed = format!(
+        "{}/{}",
+        backup.namespace().unwrap_or_default(),
+        backup.name_any()
+    );
+    hashed::resolve(&backup.spec.schedule, &seed)
+}
+
 impl Deployable for BackupCronJob {
     type Error = Error;
 
@@ -86,6 +198,7 @@ impl Deployable for BackupCronJob {
                 name: Some(self.name.clone()),
                 namespace: Some(self.ns.clone()),
                 labels: Some(labels.to_labels()),
+                annotations: Some(labels.to_annotations()),
                 owner_references: O::meta(owner).uid.clone().map(|uid| {
                     vec![OwnerReference {
                         api_version: O::api_version(&()).into_owned(),
@@ -99,11 +212,20 @@ impl Deployable for BackupCronJob {
                 ..Default::default()
             },
             spec: Some(CronJobSpec {
-                concurrency_policy: self.concurrency_policy.clone(),
+                concurrency_policy: self.concurrency_policy.map(|p| p.as_str().to_owned()),
                 failed_jobs_history_limit: self.failed_jobs_history_limit,
                 job_template: JobTemplateSpec {
-                    metadata: None,
-                    spec: Some(self.spec.clone().into()),
+                    metadata: Some(ObjectMeta {
+                        labels: Some(labels.to_labels()),
+                        annotations: Some(
+                            labels
+                                .clone()
+                                .with_annotations(self.spec.annotations().clone())
+                                .to_annotations(),
+                        ),
+                        ..Default::default()
+                    }),
+                    spec: Some(self.spec.clone().with_labels(&labels).into()),
                 },
                 schedule: self.schedule.clone(),
                 starting_deadline_seconds: self.starting_deadline_seconds,
@@ -1,20 +1,211 @@
+use k8s_openapi::api::batch::v1::Job;
 use kube::ResourceExt;
-use restic_crd::ScheduledBackup;
+use restic_crd::{BackupPhase, ReplicaStatus, ScheduledBackup, ScheduledBackupStatus};
 
 use super::{cronjob::BackupCronJob, Error};
-use crate::{deploy::Deployable, resticprofile::ResticProfile};
+use crate::{
+    backup::job::latest_pod,
+    cache::CacheClaim,
+    config::Config,
+    deploy::Deployable,
+    jobspec::{copy_names, job_phase, CopyPhases},
+    manifests::Manifests,
+    replica::{self, Replica},
+    resticprofile::ResticProfile,
+    status,
+};
 
 #[derive(Debug, Clone)]
 pub struct ScheduledBackupDeployment {
+    runs: Vec<ScheduledBackupRun>,
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledBackupRun {
+    replica: Option<Replica>,
     profile: ResticProfile,
+    manifests: Option<Manifests>,
+    cache: Option<CacheClaim>,
     job: BackupCronJob,
+    /// Names of the secondary repositories the snapshots are copied to
+    copies: Vec<String>,
 }
 
 impl ScheduledBackupDeployment {
-    pub fn new(ns: String, backup: &ScheduledBackup) -> Self {
-        let profile = ResticProfile::new(ns.clone(), backup.name_any(), &backup.spec.backup);
-        let job = BackupCronJob::new(ns, backup, profile.name());
-        Self { profile, job }
+    pub fn new(
+        ns: String,
+        backup: &ScheduledBackup,
+        replicas: &[Replica],
+        config: &Config,
+    ) -> Self {
+        let runs = replica::runs(&backup.name_any(), &backup.spec.backup, replicas)
+            .into_iter()
+            .map(|run| {
+                let profile = ResticProfile::new(ns.clone(), run.name.clone(), &run.spec);
+                let manifests = run
+                    .spec
+                    .resources
+                    .clone()
+                    .map(|r| Manifests::new(ns.clone(), &run.name, r));
+                let cache = CacheClaim::new(ns.clone(), &run.spec);
+                let copies = copy_names(&run.spec);
+                let mut job =
+                    BackupCronJob::new(ns.clone(), run.name, backup, &run.spec, profile.name());
+                if config.queue_enabled(&run.spec.restic.repository) {
+                    job = job.with_queue();
+                }
+                ScheduledBackupRun {
+                    replica: run.replica,
+                    profile,
+                    manifests,
+                    cache,
+                    job,
+                    copies,
+                }
+            })
+            .collect();
+        Self { runs }
+    }
+
+    /// Lists the jobs spawned by every run.
+    pub async fn jobs(&self, client: kube::Client) -> Result<Vec<Job>, Error> {
+        let mut jobs = Vec::new();
+        for run in &self.runs {
+            jobs.extend(run.job.jobs(client.clone()).await?);
+        }
+        Ok(jobs)
+    }
+
+    /// Creates a one-off job from every run's cronjob.
+    pub async fn run_now(&self, client: kube::Client, id: &str) -> Result<Vec<String>, Error> {
+        let mut jobs = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            jobs.push(run.job.run_now(client.clone(), id).await?);
+        }
+        Ok(jobs)
+    }
+
+    /// Reads the raw resticprofile config of every run, see [`ResticProfile::resolve`].
+    pub async fn resolve_profiles(&mut self, client: kube::Client) -> Result<(), Error> {
+        for run in &mut self.runs {
+            run.profile.resolve(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Re-renders the resticprofile config of every run if its `extraConfigFrom` key changed.
+    pub async fn refresh_profiles(&self, client: kube::Client) -> Result<(), Error> {
+        for run in &self.runs {
+            run.profile.refresh(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the Kubernetes manifests of every run fit in their secret.
+    pub async fn check_manifests(&mut self, client: kube::Client) -> Result<(), Error> {
+        for manifests in self
+            .runs
+            .iter_mut()
+            .filter_map(|run| run.manifests.as_mut())
+        {
+            manifests.check(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Re-collects the Kubernetes manifests of every run if they are stale.
+    pub async fn refresh_manifests(&self, client: kube::Client) -> Result<(), Error> {
+        for manifests in self.runs.iter().filter_map(|run| run.manifests.as_ref()) {
+            manifests.refresh(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Gets the status describing the deployed sub-resources.
+    ///
+    /// The phase of a replica is the phase of its cronjob's last job. Once the cronjob's jobs are
+    /// cleaned up, the finished phase recorded in the previous status is kept.
+    pub async fn status(
+        &self,
+        client: kube::Client,
+        backup: &ScheduledBackup,
+    ) -> Result<ScheduledBackupStatus, Error> {
+        let mut status = ScheduledBackupStatus {
+            config_map: None,
+            cron_job: None,
+            replicas: None,
+            copies: None,
+            schedule: self.runs.first().map(|run| run.job.schedule.clone()),
+            ..backup.status.clone().unwrap_or_default()
+        };
+        status::set_condition(
+            status.conditions.get_or_insert_with(Vec::new),
+            status::condition(
+                status::READY,
+                true,
+                "Deployed",
+                "Created the backup CronJobs",
+                backup.metadata.generation,
+            ),
+        );
+
+        let recorded = backup.status.as_ref();
+        let mut copies = CopyPhases::default();
+        for run in &self.runs {
+            let last_job = run.job.last_job(client.clone()).await?;
+            if !run.copies.is_empty() {
+                let pod = match &last_job {
+                    Some(job) => latest_pod(client.clone(), job).await?,
+                    None => None,
+                };
+                copies.record(
+                    &run.copies,
+                    last_job.as_ref(),
+                    pod.as_ref(),
+                    recorded.and_then(|s| s.copies.as_deref()),
+                );
+            }
+
+            match &run.replica {
+                Some(replica) => {
+                    let phase = match &last_job {
+                        Some(job) => job_phase(job),
+                        None => recorded_phase(recorded, replica),
+                    };
+                    status
+                        .replicas
+                        .get_or_insert_with(Vec::new)
+                        .push(ReplicaStatus {
+                            ordinal: replica.ordinal,
+                            claim_name: replica.claim_name.clone(),
+                            config_map: Some(run.profile.name().to_owned()),
+                            job: None,
+                            cron_job: Some(run.job.name().to_owned()),
+                            phase,
+                        });
+                }
+                None => {
+                    status.config_map = Some(run.profile.name().to_owned());
+                    status.cron_job = Some(run.job.name().to_owned());
+                }
+            }
+        }
+
+        status.copies = copies.statuses();
+        Ok(status)
+    }
+}
+
+/// Gets the finished phase recorded for a replica, or [`BackupPhase::Pending`] if it had not
+/// finished.
+fn recorded_phase(recorded: Option<&ScheduledBackupStatus>, replica: &Replica) -> BackupPhase {
+    let phase = recorded
+        .and_then(|s| s.replicas.as_ref())
+        .and_then(|r| r.iter().find(|r| r.ordinal == replica.ordinal))
+        .map(|r| &r.phase);
+    match phase {
+        Some(phase @ (BackupPhase::Completed | BackupPhase::Failed)) => phase.clone(),
+        _ => BackupPhase::Pending,
     }
 }
 
@@ -30,16 +221,71 @@ impl Deployable for ScheduledBackupDeployment {
     where
         O: kube::Resource<DynamicType = ()> + Send + Sync,
     {
-        self.profile
-            .create(client.clone(), owner, labels.clone())
-            .await?;
-        self.job.create(client, owner, labels).await?;
+        for run in &self.runs {
+            if let Some(manifests) = &run.manifests {
+                manifests
+                    .create(client.clone(), owner, labels.clone())
+                    .await?;
+            }
+            if let Some(cache) = &run.cache {
+                cache.create(client.clone(), owner, labels.clone()).await?;
+            }
+            run.profile
+                .create(client.clone(), owner, labels.clone())
+                .await?;
+            run.job
+                .create(client.clone(), owner, labels.clone())
+                .await?;
+        }
         Ok(())
     }
 
     async fn delete(&self, client: kube::Client) -> Result<(), Self::Error> {
-        self.profile.delete(client.clone()).await?;
-        self.job.delete(client).await?;
+        for run in &self.runs {
+            if let Some(manifests) = &run.manifests {
+                manifests.delete(client.clone()).await?;
+            }
+            run.profile.delete(client.clone()).await?;
+            run.job.delete(client.clone()).await?;
+            if let Some(cache) = &run.cache {
+                cache.delete(client.clone()).await?;
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_phase() {
+        let replica = |ordinal, phase| ReplicaStatus {
+            ordinal,
+            claim_name: format!("data-{ordinal}"),
+            config_map: None,
+            job: None,
+            cron_job: None,
+            phase,
+        };
+        let status = ScheduledBackupStatus {
+            replicas: Some(vec![
+                replica(0, BackupPhase::Completed),
+                replica(1, BackupPhase::Running),
+            ]),
+            ..Default::default()
+        };
+        let recorded = |ordinal| {
+            let replica = Replica {
+                ordinal,
+                claim_name: format!("data-{ordinal}"),
+            };
+            recorded_phase(Some(&status), &replica)
+        };
+
+        assert_eq!(recorded(0), BackupPhase::Completed);
+        assert_eq!(recorded(1), BackupPhase::Pending);
+        assert_eq!(recorded(2), BackupPhase::Pending);
+    }
+}
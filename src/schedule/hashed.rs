@@ -0,0 +1,123 @@
+//! Jenkins-style hashed schedules.
+//!
+//! An `H` in a cron field is replaced by a value derived from a hash of the backup, so many
+//! backups sharing the same schedule are spread across the window instead of all starting at
+//! once. The following forms are supported in every field:
+//!
+//! - `H`: a value in the field's full range, e.g. `H 2 * * *`
+//! - `H(a-b)`: a value between `a` and `b`, e.g. `H(0-29) 2 * * *`
+//! - `H/n`: every `n` units starting at a hashed offset, e.g. `H/15 * * * *`
+//! - `H(a-b)/n`: every `n` units between `a` and `b`, starting at a hashed offset
+
+use crate::hash;
+
+/// Range of values `H` resolves to in each field. Days of the month stop at 28 so the
+/// schedule runs every month.
+const RANGES: [(u32, u32); 5] = [(0, 59), (0, 23), (1, 28), (1, 12), (0, 6)];
+
+/// Replaces the `H` tokens of a schedule with concrete values derived from `seed`.
+///
+/// The result only depends on the schedule and the seed. Schedules without `H` tokens, or that
+/// are not made of five fields (such as `@daily`), are returned unchanged, as are tokens that
+/// cannot be parsed.
+pub fn resolve(schedule: &str, seed: &str) -> String {
+    let fields: Vec<_> = schedule.split_whitespace().collect();
+    if fields.len() != RANGES.len() || !fields.iter().any(|f| f.contains('H')) {
+        return schedule.to_owned();
+    }
+
+    fields
+        .iter()
+        .zip(RANGES)
+        .enumerate()
+        .map(|(i, (field, range))| {
+            let hash = hash::fnv1a(format!("{seed}#{i}").as_bytes());
+            field
+                .split(',')
+                .map(|item| resolve_item(item, range, hash).unwrap_or_else(|| item.to_owned()))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn resolve_item(item: &str, (min, max): (u32, u32), hash: u32) -> Option<String> {
+    let rest = item.strip_prefix('H')?;
+
+    let (range, step) = match rest.split_once('/') {
+        Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|s| *s > 0)?)),
+        None => (rest, None),
+    };
+    let (min, max) = if range.is_empty() {
+        (min, max)
+    } else {
+        let (a, b) = range
+            .strip_prefix('(')?
+            .strip_suffix(')')?
+            .split_once('-')?;
+        let (a, b) = (a.parse().ok()?, b.parse().ok()?);
+        if a > b || a < min || b > max {
+            return None;
+        }
+        (a, b)
+    };
+
+    Some(match step {
+        Some(step) => {
+            let offset = hash % step.min(max - min + 1);
+            format!("{}-{max}/{step}", min + offset)
+        }
+        None => (min + hash % (max - min + 1)).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_unchanged() {
+        assert_eq!(resolve("0 2 * * *", "ns/test"), "0 2 * * *");
+        assert_eq!(resolve("@daily", "ns/test"), "@daily");
+    }
+
+    #[test]
+    fn test_resolve_stable() {
+        let schedule = resolve("H H * * *", "ns/test");
+        assert_eq!(schedule, resolve("H H * * *", "ns/test"));
+
+        let fields: Vec<u32> = schedule
+            .split(' ')
+            .take(2)
+            .map(|f| f.parse().unwrap())
+            .collect();
+        assert!(fields[0] < 60);
+        assert!(fields[1] < 24);
+    }
+
+    #[test]
+    fn test_resolve_spreads() {
+        let minutes: std::collections::HashSet<_> = (0..20)
+            .map(|i| resolve("H 2 * * *", &format!("ns/backup-{i}")))
+            .collect();
+        assert!(minutes.len() > 1);
+    }
+
+    #[test]
+    fn test_resolve_item() {
+        assert_eq!(resolve_item("H", (0, 59), 61), Some("1".to_owned()));
+        assert_eq!(resolve_item("H(10-19)", (0, 59), 13), Some("13".to_owned()));
+        assert_eq!(
+            resolve_item("H/15", (0, 59), 17),
+            Some("2-59/15".to_owned())
+        );
+        assert_eq!(
+            resolve_item("H(0-29)/10", (0, 59), 7),
+            Some("7-29/10".to_owned())
+        );
+        assert_eq!(resolve_item("5", (0, 59), 7), None);
+        assert_eq!(resolve_item("H(30-70)", (0, 59), 7), None);
+        assert_eq!(resolve_item("H/0", (0, 59), 7), None);
+    }
+}
@@ -6,21 +6,34 @@ use kube::{
     Api, Client, Resource, ResourceExt,
 };
 use restic_crd::ScheduledBackup;
-use tracing::{error, info};
+use serde_json::json;
+use tracing::{error, info, warn};
 
 use crate::{
+    config,
     context::ContextData,
     deploy::{Deployable, Labels},
     finalizer::{self, FINALIZER},
-    Error,
+    queue, quiesce,
+    replica::{self, Replica},
+    repository, status, Error,
 };
 
-mod cronjob;
+pub mod cronjob;
 mod deploy;
+mod hashed;
+mod validate;
 
-pub async fn run_controller(client: Client) {
+/// Annotation requesting an immediate run of a [`ScheduledBackup`]. A new run is started each
+/// time the value changes, so a timestamp is a good choice.
+pub const RUN_NOW_ANNOTATION: &str = "restic.anshulg.com/run-now";
+
+/// Reason of the `Ready` condition when the manifests to backup do not fit in a Secret.
+const MANIFESTS_TOO_LARGE: &str = "ManifestsTooLarge";
+
+pub async fn run_controller(client: Client, config: config::Config) {
     let crd_api: Api<ScheduledBackup> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client));
+    let context: Arc<ContextData> = Arc::new(ContextData::new(client, config));
 
     Controller::new(crd_api, Config::default())
         .run(reconcile, on_error, context)
@@ -46,27 +59,84 @@ async fn reconcile(
     let ns = backup.namespace().ok_or(Error::MissingNamespace)?;
     let name = backup.name_any();
 
+    // Repositories given by reference are read from their Secret, which may be gone on deletion
+    let mut backup = Arc::unwrap_or_clone(backup);
+    let spec = &mut backup.spec.backup;
+    if let Err(err) = repository::resolve(client.clone(), &backup.metadata, spec).await {
+        if backup.meta().deletion_timestamp.is_none() {
+            return Err(err);
+        }
+        warn!(name, %err, "Could not read the repository of a deleted backup");
+    }
+
     match determine_action(&backup) {
         ScheduledBackupAction::Create => {
+            let api = Api::<ScheduledBackup>::namespaced(client.clone(), &ns);
+
+            // Kubernetes would reject the CronJob, so wait for the spec to be fixed
+            if let Err(err) = validate(&backup) {
+                warn!(name, %err, "Invalid schedule");
+                set_ready(&api, &backup, false, "InvalidSchedule", err.to_string()).await?;
+                return Ok(Action::await_change());
+            }
+
+            let replicas = replica::list(client.clone(), &ns, &backup.spec.backup).await?;
+            let mut deployment =
+                deploy::ScheduledBackupDeployment::new(ns, &backup, &replicas, &context.config);
+
+            // The API server would reject the secret, so wait for fewer resources to backup
+            if let Err(err) = deployment.check_manifests(client.clone()).await {
+                if !matches!(err, Error::ManifestsTooLarge { .. }) {
+                    return Err(err);
+                }
+                warn!(name, %err, "Manifests too large");
+                set_ready(&api, &backup, false, MANIFESTS_TOO_LARGE, err.to_string()).await?;
+                return Ok(Action::requeue(Duration::from_secs(300)));
+            }
+
+            // Wait for the raw config to be fixed, before anything is created
+            if let Err(err) = deployment.resolve_profiles(client.clone()).await {
+                if !err.is_invalid_extra_config() {
+                    return Err(err);
+                }
+                warn!(name, %err, "Invalid extra config");
+                let reason = status::INVALID_EXTRA_CONFIG;
+                set_ready(&api, &backup, false, reason, err.to_string()).await?;
+                return Ok(Action::requeue(Duration::from_secs(60)));
+            }
+
             // Add the finalizer to the resource
-            finalizer::add(
-                &Api::<ScheduledBackup>::namespaced(client.clone(), &ns),
-                &name,
-            )
-            .await?;
+            finalizer::add(&api, &name).await?;
 
             // Create the deployment
-            let deployment = deploy::ScheduledBackupDeployment::new(ns, &backup);
-            let labels = Labels::new(name);
-            deployment.create(client, &*backup, labels).await?;
+            let labels = Labels::new(name.clone())
+                .with_labels(backup.labels().clone())
+                .with_common(&backup.spec.backup);
+            deployment.create(client.clone(), &backup, labels).await?;
+
+            // Record the created sub-resources
+            let status = deployment.status(client, &backup).await?;
+            status::patch(&api, &name, &status).await?;
 
             Ok(Action::requeue(Duration::from_secs(10)))
         }
         ScheduledBackupAction::Delete => {
             // Delete the deployment
-            let deployment = deploy::ScheduledBackupDeployment::new(ns.clone(), &backup);
+            let deployment = deploy::ScheduledBackupDeployment::new(
+                ns.clone(),
+                &backup,
+                &recorded_replicas(&backup),
+                &context.config,
+            );
+            let jobs = deployment.jobs(client.clone()).await?;
             deployment.delete(client.clone()).await?;
 
+            // Scale up the workload if a running job had quiesced it
+            if let Some(q) = &backup.spec.backup.quiesce {
+                let jobs: Vec<_> = jobs.iter().map(ResourceExt::name_any).collect();
+                quiesce::release(client.clone(), &ns, q, &jobs).await?;
+            }
+
             // Remove the finalizer from the resource
             finalizer::remove(
                 &Api::<ScheduledBackup>::namespaced(client.clone(), &ns),
@@ -75,8 +145,135 @@ async fn reconcile(
             .await?;
             Ok(Action::requeue(Duration::from_secs(10)))
         }
-        ScheduledBackupAction::Noop => Ok(Action::requeue(Duration::from_secs(10))),
+        ScheduledBackupAction::Noop => {
+            let deployment = deploy::ScheduledBackupDeployment::new(
+                ns.clone(),
+                &backup,
+                &recorded_replicas(&backup),
+                &context.config,
+            );
+
+            let api = Api::<ScheduledBackup>::namespaced(client.clone(), &ns);
+            let jobs = deployment.jobs(client.clone()).await?;
+
+            if let Some(q) = &backup.spec.backup.quiesce {
+                // The cronjob's next job runs again, so the deleted one is not recorded
+                if let Some(job) = quiesce::reconcile(client.clone(), &ns, q, &jobs).await? {
+                    quiesce::abandon(client.clone(), &ns, q, &job).await?;
+                }
+            }
+
+            match deployment.refresh_manifests(client.clone()).await {
+                Ok(()) if ready_reason(&backup) == Some(MANIFESTS_TOO_LARGE) => {
+                    let message = "Created the backup CronJobs";
+                    set_ready(&api, &backup, true, "Deployed", message).await?;
+                }
+                Ok(()) => {}
+                // The backups keep using the manifests collected last
+                Err(err @ Error::ManifestsTooLarge { .. }) => {
+                    warn!(name, %err, "Manifests too large");
+                    set_ready(&api, &backup, false, MANIFESTS_TOO_LARGE, err.to_string()).await?;
+                }
+                Err(err) => return Err(err),
+            }
+
+            match deployment.refresh_profiles(client.clone()).await {
+                Ok(()) if ready_reason(&backup) == Some(status::INVALID_EXTRA_CONFIG) => {
+                    let message = "Created the backup CronJobs";
+                    set_ready(&api, &backup, true, "Deployed", message).await?;
+                }
+                Ok(()) => {}
+                // The backups keep using the config rendered last
+                Err(err) if err.is_invalid_extra_config() => {
+                    warn!(name, %err, "Invalid extra config");
+                    let reason = status::INVALID_EXTRA_CONFIG;
+                    set_ready(&api, &backup, false, reason, err.to_string()).await?;
+                }
+                Err(err) => return Err(err),
+            }
+
+            if let Some(id) = run_now_requested(&backup) {
+                info!(name, id, "Starting manual run");
+                let jobs = deployment.run_now(client.clone(), id).await?;
+                status::patch(&api, &name, &json!({ "runNow": id, "manualJobs": jobs })).await?;
+            }
+
+            let queue_position = jobs.iter().filter_map(queue::queue_position).min();
+            if backup.status.as_ref().and_then(|s| s.queue_position) != queue_position {
+                status::patch(&api, &name, &json!({ "queuePosition": queue_position })).await?;
+            }
+
+            let current = deployment.status(client, &backup).await?;
+            let recorded = backup.status.as_ref();
+            if recorded.and_then(|s| s.replicas.as_ref()) != current.replicas.as_ref()
+                || recorded.and_then(|s| s.copies.as_ref()) != current.copies.as_ref()
+            {
+                let patch = json!({ "replicas": current.replicas, "copies": current.copies });
+                status::patch(&api, &name, &patch).await?;
+            }
+
+            Ok(Action::requeue(Duration::from_secs(10)))
+        }
+    }
+}
+
+/// Checks that Kubernetes will accept the CronJob schedule and time zone of the backup.
+pub fn validate(backup: &ScheduledBackup) -> Result<(), Error> {
+    validate::validate(&cronjob::schedule(backup), backup.spec.time_zone.as_deref())
+}
+
+/// Sets the `Ready` condition of the backup if it changed.
+async fn set_ready(
+    api: &Api<ScheduledBackup>,
+    backup: &ScheduledBackup,
+    ready: bool,
+    reason: &str,
+    message: impl Into<String>,
+) -> Result<(), Error> {
+    let mut conditions = backup
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.clone())
+        .unwrap_or_default();
+    let condition = status::condition(
+        status::READY,
+        ready,
+        reason,
+        message,
+        backup.metadata.generation,
+    );
+    if status::set_condition(&mut conditions, condition) {
+        let patch = json!({ "conditions": conditions });
+        status::patch(api, &backup.name_any(), &patch).await?;
     }
+    Ok(())
+}
+
+/// Gets the reason of the backup's `Ready` condition.
+fn ready_reason(backup: &ScheduledBackup) -> Option<&str> {
+    backup
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .and_then(|c| c.iter().find(|c| c.type_ == status::READY))
+        .map(|c| c.reason.as_str())
+}
+
+/// Gets the StatefulSet replicas recorded in the status when the backup was created.
+fn recorded_replicas(backup: &ScheduledBackup) -> Vec<Replica> {
+    backup
+        .status
+        .as_ref()
+        .and_then(|s| s.replicas.as_ref())
+        .map(|r| r.iter().map(Replica::from).collect())
+        .unwrap_or_default()
+}
+
+/// Gets the value of the run-now annotation if it has not been handled yet.
+fn run_now_requested(backup: &ScheduledBackup) -> Option<&str> {
+    let id = backup.annotations().get(RUN_NOW_ANNOTATION)?;
+    let handled = backup.status.as_ref().and_then(|s| s.run_now.as_ref());
+    (handled != Some(id)).then_some(id.as_str())
 }
 
 fn determine_action(backup: &ScheduledBackup) -> ScheduledBackupAction {
@@ -108,3 +305,66 @@ enum ScheduledBackupAction {
     /// No operation required. Update the status if needed.
     Noop,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use restic_crd::{
+        BackupSpec, Repository, ResticConfig, ScheduledBackupSpec, ScheduledBackupStatus,
+    };
+
+    use super::*;
+
+    fn create_backup(annotation: Option<&str>, handled: Option<&str>) -> ScheduledBackup {
+        let spec = ScheduledBackupSpec::builder()
+            .schedule("0 2 * * *".to_owned())
+            .backup(
+                BackupSpec::builder()
+                    .restic(
+                        ResticConfig::builder()
+                            .repository(
+                                Repository::builder()
+                                    .r#type(restic_crd::RepositoryType::Rest)
+                                    .uri("https://example.com".to_owned())
+                                    .password(Default::default())
+                                    .build(),
+                            )
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let mut backup = ScheduledBackup::new("test", spec);
+        backup.metadata.annotations =
+            annotation.map(|a| BTreeMap::from([(RUN_NOW_ANNOTATION.to_owned(), a.to_owned())]));
+        backup.status = Some(ScheduledBackupStatus {
+            run_now: handled.map(ToOwned::to_owned),
+            ..Default::default()
+        });
+        backup
+    }
+
+    #[test]
+    fn test_run_now_requested() {
+        assert_eq!(run_now_requested(&create_backup(None, None)), None);
+        assert_eq!(
+            run_now_requested(&create_backup(Some("2024-12-20T10:00:00Z"), None)),
+            Some("2024-12-20T10:00:00Z")
+        );
+        assert_eq!(
+            run_now_requested(&create_backup(
+                Some("2024-12-20T10:00:00Z"),
+                Some("2024-12-20T10:00:00Z")
+            )),
+            None
+        );
+        assert_eq!(
+            run_now_requested(&create_backup(
+                Some("2024-12-21T10:00:00Z"),
+                Some("2024-12-20T10:00:00Z")
+            )),
+            Some("2024-12-21T10:00:00Z")
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use chrono_tz::Tz;
+use croner::parser::{CronParser, Seconds, Year};
+
+use super::Error;
+
+/// Checks that the CronJob schedule and time zone will be accepted by Kubernetes.
+///
+/// `schedule` must already have its `H` tokens resolved.
+pub fn validate(schedule: &str, time_zone: Option<&str>) -> Result<(), Error> {
+    if let Some(token) = unsupported_token(schedule) {
+        return Err(Error::UnsupportedScheduleToken {
+            schedule: schedule.to_owned(),
+            token,
+        });
+    }
+
+    // Kubernetes only accepts the standard five fields
+    let parser = CronParser::builder()
+        .seconds(Seconds::Disallowed)
+        .year(Year::Disallowed)
+        .build();
+    parser
+        .parse(schedule)
+        .map_err(|source| Error::InvalidSchedule {
+            schedule: schedule.to_owned(),
+            source,
+        })?;
+
+    if let Some(time_zone) = time_zone {
+        time_zone
+            .parse::<Tz>()
+            .map_err(|_| Error::InvalidTimeZone(time_zone.to_owned()))?;
+    }
+
+    Ok(())
+}
+
+/// Finds a token of the day fields that croner accepts, but the cron parser of Kubernetes does
+/// not: `L` (last), `W` (nearest weekday) and `#` (nth weekday of the month).
+fn unsupported_token(schedule: &str) -> Option<char> {
+    let fields: Vec<_> = schedule.split_whitespace().collect();
+    let [_, _, day_of_month, _, day_of_week] = fields[..] else {
+        return None;
+    };
+
+    // `W` is part of `WED`
+    let mut day_of_week = day_of_week.to_uppercase();
+    for day in ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"] {
+        day_of_week = day_of_week.replace(day, "");
+    }
+    [day_of_month.to_uppercase(), day_of_week]
+        .iter()
+        .find_map(|field| field.chars().find(|c| matches!(c, 'L' | 'W' | '#')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_schedule() {
+        assert!(validate("0 2 * * *", None).is_ok());
+        assert!(validate("*/15 1-5 * JAN MON-FRI", None).is_ok());
+        assert!(validate("@daily", None).is_ok());
+
+        assert!(matches!(
+            validate("0 2 * *", None),
+            Err(Error::InvalidSchedule { .. })
+        ));
+        assert!(matches!(
+            validate("0 0 2 * * *", None),
+            Err(Error::InvalidSchedule { .. })
+        ));
+        assert!(matches!(
+            validate("61 2 * * *", None),
+            Err(Error::InvalidSchedule { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_unsupported_tokens() {
+        assert!(validate("0 2 * JUL WED", None).is_ok());
+
+        assert!(matches!(
+            validate("0 2 L * *", None),
+            Err(Error::UnsupportedScheduleToken { token: 'L', .. })
+        ));
+        assert!(matches!(
+            validate("0 2 * * 5L", None),
+            Err(Error::UnsupportedScheduleToken { token: 'L', .. })
+        ));
+        assert!(matches!(
+            validate("0 2 15W * *", None),
+            Err(Error::UnsupportedScheduleToken { token: 'W', .. })
+        ));
+        assert!(matches!(
+            validate("0 2 * * MON#1", None),
+            Err(Error::UnsupportedScheduleToken { token: '#', .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_time_zone() {
+        assert!(validate("0 2 * * *", Some("America/Los_Angeles")).is_ok());
+        assert!(validate("0 2 * * *", Some("Etc/UTC")).is_ok());
+
+        assert!(matches!(
+            validate("0 2 * * *", Some("Mars/Olympus_Mons")),
+            Err(Error::InvalidTimeZone(tz)) if tz == "Mars/Olympus_Mons"
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+use k8s_openapi::{
+    apimachinery::pkg::apis::meta::v1::{Condition, Time},
+    chrono::Utc,
+};
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Error,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+/// Merges the given status into the status subresource of the given resource.
+pub async fn patch<K, S>(api: &Api<K>, name: &str, status: &S) -> Result<K, Error>
+where
+    K: Clone + DeserializeOwned + std::fmt::Debug,
+    S: Serialize,
+{
+    let status = json!({ "status": status });
+
+    let patch = Patch::Merge(&status);
+    api.patch_status(name, &PatchParams::default(), &patch)
+        .await
+}
+
+/// Condition reporting whether a resource's sub-resources are deployed.
+pub const READY: &str = "Ready";
+/// Reason of the `Ready` condition when `resticProfile.extraConfigFrom` cannot be read.
+pub const INVALID_EXTRA_CONFIG: &str = "InvalidExtraConfig";
+
+/// Creates a condition that transitioned now.
+pub fn condition(
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: impl Into<String>,
+    generation: Option<i64>,
+) -> Condition {
+    Condition {
+        last_transition_time: Time(Utc::now()),
+        message: message.into(),
+        observed_generation: generation,
+        reason: reason.to_owned(),
+        status: if status { "True" } else { "False" }.to_owned(),
+        type_: type_.to_owned(),
+    }
+}
+
+/// Replaces the condition of the same type, keeping its transition time if its status is
+/// unchanged.
+///
+/// Returns whether the conditions changed.
+pub fn set_condition(conditions: &mut Vec<Condition>, mut condition: Condition) -> bool {
+    match conditions.iter_mut().find(|c| c.type_ == condition.type_) {
+        Some(existing) => {
+            if existing.status == condition.status {
+                condition.last_transition_time = existing.last_transition_time.clone();
+            }
+            let changed = *existing != condition;
+            *existing = condition;
+            changed
+        }
+        None => {
+            conditions.push(condition);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_condition() {
+        let mut conditions = Vec::new();
+        let invalid = condition(READY, false, "InvalidSchedule", "bad", Some(1));
+        assert!(set_condition(&mut conditions, invalid.clone()));
+        assert!(!set_condition(
+            &mut conditions,
+            condition(READY, false, "InvalidSchedule", "bad", Some(1))
+        ));
+        assert_eq!(conditions, vec![invalid.clone()]);
+
+        assert!(set_condition(
+            &mut conditions,
+            condition(READY, true, "Deployed", "ok", Some(2))
+        ));
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].status, "True");
+    }
+}
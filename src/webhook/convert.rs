@@ -0,0 +1,131 @@
+use kube::core::{
+    conversion::{ConversionRequest, ConversionResponse, ConversionReview},
+    response::Status,
+};
+use restic_crd::v1beta1;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::Error;
+
+const V1ALPHA1: &str = "restic.anshulg.com/v1alpha1";
+const V1BETA1: &str = "restic.anshulg.com/v1beta1";
+
+/// Answers a conversion review by converting every object to the desired API version.
+pub fn review(body: &[u8]) -> Result<ConversionReview, serde_json::Error> {
+    let review: ConversionReview = serde_json::from_slice(body)?;
+    let req = match ConversionRequest::from_review(review) {
+        Ok(req) => req,
+        Err(err) => {
+            let status = Status::failure(&err.to_string(), "InvalidRequest");
+            return Ok(ConversionResponse::invalid(status).into_review());
+        }
+    };
+
+    let desired = req.desired_api_version.clone();
+    let objects = req.objects.clone();
+    let res = ConversionResponse::for_request(req);
+    let converted: Result<Vec<_>, _> = objects
+        .into_iter()
+        .map(|object| convert(object, &desired))
+        .collect();
+
+    Ok(match converted {
+        Ok(objects) => res.success(objects),
+        Err(err) => res.failure(Status::failure(&err.to_string(), "ConversionFailed")),
+    }
+    .into_review())
+}
+
+/// Converts a `Backup` or `ScheduledBackup` to the given API version.
+fn convert(object: Value, desired: &str) -> Result<Value, Error> {
+    if object["apiVersion"] == desired {
+        return Ok(object);
+    }
+
+    let kind = object["kind"].as_str().unwrap_or_default().to_owned();
+    match (kind.as_str(), desired) {
+        ("Backup", V1BETA1) => via::<restic_crd::Backup, v1beta1::Backup>(object),
+        ("Backup", V1ALPHA1) => via::<v1beta1::Backup, restic_crd::Backup>(object),
+        ("ScheduledBackup", V1BETA1) => {
+            via::<restic_crd::ScheduledBackup, v1beta1::ScheduledBackup>(object)
+        }
+        ("ScheduledBackup", V1ALPHA1) => {
+            via::<v1beta1::ScheduledBackup, restic_crd::ScheduledBackup>(object)
+        }
+        _ => Err(Error::UnsupportedConversion {
+            kind,
+            version: desired.to_owned(),
+        }),
+    }
+}
+
+fn via<From, To>(object: Value) -> Result<Value, Error>
+where
+    From: DeserializeOwned,
+    To: std::convert::From<From> + Serialize,
+{
+    let object: From = serde_json::from_value(object)?;
+    let mut object = serde_json::to_value(To::from(object))?;
+    strip_nulls(&mut object);
+    Ok(object)
+}
+
+/// Removes the `null` fields that unset options serialize to, so converted objects only contain
+/// the fields that were set.
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn backup() -> Value {
+        json!({
+            "apiVersion": V1ALPHA1,
+            "kind": "Backup",
+            "metadata": { "name": "test", "namespace": "default", "uid": "1234" },
+            "spec": {
+                "restic": {
+                    "repository": {
+                        "type": "rest",
+                        "uri": "https://example.com",
+                        "password": { "name": "secret", "key": "password" }
+                    },
+                    "compression": "auto"
+                }
+            },
+            "status": { "phase": "Completed" }
+        })
+    }
+
+    #[test]
+    fn test_convert() {
+        let beta = convert(backup(), V1BETA1).unwrap();
+        assert_eq!(beta["apiVersion"], V1BETA1);
+        assert_eq!(beta["metadata"]["uid"], "1234");
+        assert_eq!(beta["spec"]["repository"]["uri"], "https://example.com");
+        assert_eq!(beta["status"]["phase"], "Completed");
+
+        let alpha = convert(beta, V1ALPHA1).unwrap();
+        assert_eq!(alpha["spec"], backup()["spec"]);
+    }
+
+    #[test]
+    fn test_convert_unsupported() {
+        assert!(matches!(
+            convert(backup(), "restic.anshulg.com/v2"),
+            Err(Error::UnsupportedConversion { .. })
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use kube::{
+    core::{
+        admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+        DynamicObject,
+    },
+    Client, Resource,
+};
+use restic_crd::{Backup, NodeBackup, ScheduledBackup};
+use serde::de::DeserializeOwned;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+use crate::{config::Config, Error};
+
+mod convert;
+mod tls;
+mod validate;
+
+/// Path of the validating webhook for [`Backup`] resources.
+pub const BACKUP_PATH: &str = "/validate/backup";
+/// Path of the validating webhook for [`ScheduledBackup`] resources.
+pub const SCHEDULED_BACKUP_PATH: &str = "/validate/scheduled-backup";
+/// Path of the validating webhook for [`NodeBackup`] resources.
+pub const NODE_BACKUP_PATH: &str = "/validate/node-backup";
+/// Path of the conversion webhook between the API versions of the CRDs.
+pub const CONVERT_PATH: &str = "/convert";
+
+/// Serves the validating admission and CRD conversion webhooks over HTTPS on the given port.
+pub async fn run(client: Client, config: Config, port: u16) -> Result<(), Error> {
+    let tls = tls::server_config(client, &config).await?;
+    let acceptor = TlsAcceptor::from(Arc::new(tls));
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    info!(port, "Webhook server listening");
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(%err, %addr, "Webhook TLS handshake failed");
+                    return;
+                }
+            };
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service_fn(handle))
+                .await
+            {
+                warn!(%err, %addr, "Webhook connection error");
+            }
+        });
+    }
+}
+
+async fn handle(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.method() != Method::POST {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let path = req.uri().path().to_owned();
+    let Ok(body) = req.into_body().collect().await.map(|b| b.to_bytes()) else {
+        return Ok(status(StatusCode::BAD_REQUEST));
+    };
+
+    let review = match path.as_str() {
+        BACKUP_PATH => review::<Backup>(&body, |b| validate::backup(&b.spec))
+            .and_then(|r| serde_json::to_vec(&r)),
+        SCHEDULED_BACKUP_PATH => review::<ScheduledBackup>(&body, validate::scheduled_backup)
+            .and_then(|r| serde_json::to_vec(&r)),
+        NODE_BACKUP_PATH => {
+            review::<NodeBackup>(&body, validate::node_backup).and_then(|r| serde_json::to_vec(&r))
+        }
+        CONVERT_PATH => convert::review(&body).and_then(|r| serde_json::to_vec(&r)),
+        _ => return Ok(status(StatusCode::NOT_FOUND)),
+    };
+
+    Ok(match review {
+        Ok(body) => Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(err) => {
+            warn!(%err, path, "Invalid webhook review");
+            status(StatusCode::BAD_REQUEST)
+        }
+    })
+}
+
+/// Answers an admission review, denying the object if `validate` finds any problems.
+fn review<K>(
+    body: &[u8],
+    validate: impl Fn(&K) -> Vec<String>,
+) -> Result<AdmissionReview<DynamicObject>, serde_json::Error>
+where
+    K: Resource + DeserializeOwned,
+{
+    let review: AdmissionReview<K> = serde_json::from_slice(body)?;
+    let req: AdmissionRequest<K> = match review.try_into() {
+        Ok(req) => req,
+        Err(err) => return Ok(AdmissionResponse::invalid(err).into_review()),
+    };
+
+    let mut res = AdmissionResponse::from(&req);
+    if let Some(object) = &req.object {
+        let problems = validate(object);
+        if !problems.is_empty() {
+            res = res.deny(problems.join("; "));
+        }
+    }
+    Ok(res.into_review())
+}
+
+fn status(code: StatusCode) -> Response<Full<Bytes>> {
+    let mut res = Response::new(Full::default());
+    *res.status_mut() = code;
+    res
+}
@@ -0,0 +1,118 @@
+use std::{path::Path, sync::Arc};
+
+use k8s_openapi::{
+    api::admissionregistration::v1::ValidatingWebhookConfiguration,
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition, ByteString,
+};
+use kube::{
+    api::{Patch, PatchParams, PostParams},
+    Api, Client, CustomResourceExt,
+};
+use restic_crd::{Backup, ScheduledBackup};
+use serde_json::json;
+use tokio_rustls::rustls::{crypto::ring, ServerConfig};
+use tracing::info;
+
+use crate::{config::Config, Error};
+
+const CERT_FILE: &str = "tls.crt";
+const KEY_FILE: &str = "tls.key";
+
+/// Builds the TLS config of the webhook server.
+///
+/// The certificate is read from `tls.crt` and `tls.key` in the certificate directory, where
+/// cert-manager mounts its secrets. Without them, a self-signed certificate is generated for
+/// the webhook service, and its CA bundle is injected into the
+/// `ValidatingWebhookConfiguration` and the conversion webhooks of the CRDs. The certificate is
+/// only read at startup.
+pub async fn server_config(client: Client, config: &Config) -> Result<ServerConfig, Error> {
+    let dir = config.webhook_cert_dir();
+    let (cert, key) = match read_pair(&dir).await {
+        Some(pair) => {
+            info!(dir = %dir.display(), "Using webhook certificate");
+            pair
+        }
+        None => self_signed(client, config, &dir).await?,
+    };
+
+    let certs = rustls_pemfile::certs(&mut cert.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut key.as_slice())?
+        .ok_or_else(|| Error::MissingWebhookCertificate(dir.join(KEY_FILE)))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(config)
+}
+
+async fn read_pair(dir: &Path) -> Option<(Vec<u8>, Vec<u8>)> {
+    let cert = tokio::fs::read(dir.join(CERT_FILE)).await.ok()?;
+    let key = tokio::fs::read(dir.join(KEY_FILE)).await.ok()?;
+    Some((cert, key))
+}
+
+async fn self_signed(
+    client: Client,
+    config: &Config,
+    dir: &Path,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let service = config
+        .webhook_service
+        .clone()
+        .ok_or_else(|| Error::MissingWebhookCertificate(dir.join(CERT_FILE)))?;
+    info!(service, "Generating self-signed webhook certificate");
+
+    let certified = rcgen::generate_simple_self_signed(vec![service])?;
+    let cert = certified.cert.pem();
+    let key = certified.key_pair.serialize_pem();
+
+    if let Some(name) = &config.webhook_configuration {
+        inject_ca_bundle(client.clone(), name, &cert).await?;
+    }
+    for crd in [Backup::crd_name(), ScheduledBackup::crd_name()] {
+        inject_conversion_ca_bundle(client.clone(), crd, &cert).await?;
+    }
+
+    Ok((cert.into_bytes(), key.into_bytes()))
+}
+
+/// Sets the CA bundle of every webhook of the configuration to the given certificate.
+async fn inject_ca_bundle(client: Client, name: &str, cert: &str) -> Result<(), Error> {
+    let api: Api<ValidatingWebhookConfiguration> = Api::all(client);
+    let mut configuration = api.get(name).await?;
+    for webhook in configuration.webhooks.iter_mut().flatten() {
+        webhook.client_config.ca_bundle = Some(ByteString(cert.as_bytes().to_vec()));
+    }
+    api.replace(name, &PostParams::default(), &configuration)
+        .await?;
+
+    info!(name, "Injected CA bundle into webhook configuration");
+    Ok(())
+}
+
+/// Sets the CA bundle of the CRD's conversion webhook, if it converts through a webhook.
+async fn inject_conversion_ca_bundle(client: Client, name: &str, cert: &str) -> Result<(), Error> {
+    let api: Api<CustomResourceDefinition> = Api::all(client);
+    let Some(crd) = api.get_opt(name).await? else {
+        return Ok(());
+    };
+    if crd.spec.conversion.is_none_or(|c| c.strategy != "Webhook") {
+        return Ok(());
+    }
+
+    let patch = json!({
+        "spec": {
+            "conversion": {
+                "webhook": {
+                    "clientConfig": { "caBundle": ByteString(cert.as_bytes().to_vec()) }
+                }
+            }
+        }
+    });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+
+    info!(name, "Injected CA bundle into CRD conversion webhook");
+    Ok(())
+}
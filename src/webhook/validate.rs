@@ -0,0 +1,588 @@
+use restic_crd::{BackupSpec, Compression, NodeBackup, RestartPolicy, Retention, ScheduledBackup};
+
+use crate::{
+    jobspec, node,
+    resticprofile::{
+        self,
+        config::{DEFAULT_GROUP, DEFAULT_PROFILE},
+    },
+    schedule,
+};
+
+/// Checks a backup spec for mistakes that would otherwise only surface when the backup runs.
+///
+/// Returns a message for each problem found.
+pub fn backup(spec: &BackupSpec) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // StatefulSet replicas and manifests bring their own sources
+    let has_source = spec.stateful_set.is_some() || spec.resources.is_some();
+    let mounts = spec.volume.as_ref().map_or(&[][..], |v| &v.mounts);
+    if mounts.is_empty() && !has_source {
+        problems.push(
+            "volume.mounts must not be empty when there is no statefulSet or resources to back up"
+                .to_owned(),
+        );
+    }
+    if let Some(volume) = &spec.volume {
+        for mount in &volume.mounts {
+            if !volume.volumes.iter().any(|v| v.name == mount.name) {
+                problems.push(format!(
+                    "volume.mounts: no volume named {:?} in volume.volumes",
+                    mount.name
+                ));
+            }
+        }
+        for name in volume.tags.iter().flat_map(|t| t.keys()) {
+            if !volume.mounts.iter().any(|m| &m.name == name) {
+                problems.push(format!(
+                    "volume.tags: no mount named {name:?} in volume.mounts"
+                ));
+            }
+        }
+        if let Some(prefix) = &volume.path_prefix {
+            if !prefix.starts_with('/') {
+                problems.push(format!(
+                    "volume.pathPrefix: {prefix:?} must be an absolute path"
+                ));
+            }
+        }
+    }
+
+    let restic = &spec.restic;
+    if restic.repository.version == Some(1) && restic.compression != Compression::Auto {
+        problems.push(format!(
+            "restic.compression: {} requires repository format version 2",
+            restic.compression.as_str()
+        ));
+    }
+
+    if let Some(retention) = &restic.retention {
+        if retention.prune && !has_keep_policy(retention) {
+            problems.push(
+                "restic.retention: prune is set without any keep policy, so nothing would be forgotten"
+                    .to_owned(),
+            );
+        }
+        for (field, duration) in keep_within(retention) {
+            if let Some(duration) = duration.filter(|d| !is_valid_duration(d)) {
+                problems.push(format!(
+                    "restic.retention.{field}: invalid duration {duration:?}, expected e.g. 2y5m7d3h"
+                ));
+            }
+        }
+        if let Some(group_by) = retention
+            .group_by
+            .as_ref()
+            .filter(|g| !is_valid_group_by(g))
+        {
+            problems.push(format!(
+                "restic.retention.groupBy: invalid grouping {group_by:?}, expected a comma-separated list of host, paths and tags"
+            ));
+        }
+    }
+
+    let profiles = restic.profiles.as_deref().unwrap_or_default();
+    for (i, profile) in profiles.iter().enumerate() {
+        let name = &profile.name;
+        if resticprofile::is_reserved_profile(name) {
+            problems.push(format!(
+                "restic.profiles: profile name {name:?} is reserved"
+            ));
+        }
+        if profiles[..i].iter().any(|p| &p.name == name) {
+            problems.push(format!(
+                "restic.profiles: profile name {name:?} is used more than once"
+            ));
+        }
+        if profile.paths.is_empty() {
+            problems.push(format!("restic.profiles: profile {name:?} has no paths"));
+        }
+    }
+    for (group, members) in restic.groups.iter().flatten() {
+        if group == DEFAULT_GROUP {
+            problems.push(format!("restic.groups: group name {group:?} is reserved"));
+        }
+        for member in members {
+            if member != DEFAULT_PROFILE && !profiles.iter().any(|p| &p.name == member) {
+                problems.push(format!(
+                    "restic.groups: group {group:?} has unknown profile {member:?}"
+                ));
+            }
+        }
+    }
+
+    if restic
+        .backup
+        .as_ref()
+        .is_some_and(|b| b.exclude_cloud_files)
+    {
+        problems.push(
+            "restic.backup.excludeCloudFiles is only supported by restic on Windows, but backups run in Linux pods"
+                .to_owned(),
+        );
+    }
+    if let Some(size) = restic
+        .backup
+        .as_ref()
+        .and_then(|b| b.exclude_larger_than.as_ref())
+    {
+        if !is_valid_size(size) {
+            problems.push(format!(
+                "restic.backup.excludeLargerThan: invalid size {size:?}, expected a number with an optional k, m, g or t suffix"
+            ));
+        }
+    }
+
+    let secondary = restic.secondary_repositories.as_deref().unwrap_or_default();
+    for (i, repo) in secondary.iter().enumerate() {
+        let name = &repo.name;
+        if !is_dns_label(name) || name.len() > 51 {
+            problems.push(format!(
+                "restic.secondaryRepositories: name {name:?} must be a DNS label of at most 51 characters"
+            ));
+        }
+        if secondary[..i].iter().any(|r| &r.name == name) {
+            problems.push(format!(
+                "restic.secondaryRepositories: name {name:?} is used more than once"
+            ));
+        }
+    }
+
+    if let Some(rpcfg) = &spec.restic_profile {
+        let has_extra_containers = rpcfg
+            .extra_containers
+            .as_ref()
+            .is_some_and(|c| !c.is_empty());
+        if has_extra_containers && !secondary.is_empty() {
+            problems.push(
+                "resticProfile.extraContainers cannot be used with restic.secondaryRepositories, use init containers with restartPolicy: Always for sidecars"
+                    .to_owned(),
+            );
+        }
+        let operator_names: Vec<_> = std::iter::once(jobspec::CONTAINER_NAME.to_owned())
+            .chain(
+                secondary
+                    .iter()
+                    .map(|r| jobspec::copy_container_name(&r.name)),
+            )
+            .collect();
+        let containers = rpcfg.init_containers.iter().chain(&rpcfg.extra_containers);
+        let mut names: Vec<_> = containers.flatten().map(|c| c.name.as_str()).collect();
+        names.sort_unstable();
+        for (i, name) in names.iter().enumerate() {
+            if operator_names.iter().any(|n| n == name) || names[..i].last() == Some(name) {
+                problems.push(format!(
+                    "resticProfile: container name {name:?} is used more than once"
+                ));
+            }
+        }
+    }
+
+    if let Some(policy) = &spec.pod_failure_policy {
+        let restart_policy = spec.restic_profile.as_ref().and_then(|p| p.restart_policy);
+        if restart_policy == Some(RestartPolicy::OnFailure) {
+            problems.push(
+                "podFailurePolicy requires resticProfile.restartPolicy to be Never".to_owned(),
+            );
+        }
+
+        let codes = [&policy.fail_job_exit_codes, &policy.ignore_exit_codes];
+        if codes.iter().copied().flatten().flatten().any(|c| *c == 0) {
+            problems.push("podFailurePolicy: exit code 0 is not a failure".to_owned());
+        }
+    }
+
+    problems
+}
+
+/// Checks a scheduled backup's schedule and backup spec.
+pub fn scheduled_backup(backup: &ScheduledBackup) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Err(err) = schedule::validate(backup) {
+        problems.push(err.to_string());
+    }
+    problems.extend(self::backup(&backup.spec.backup));
+    problems
+}
+
+/// Checks a node backup's host paths, schedule and backup spec.
+pub fn node_backup(backup: &NodeBackup) -> Vec<String> {
+    let mut problems = Vec::new();
+    let spec = &backup.spec;
+    if spec.paths.is_empty() {
+        problems.push("paths must not be empty".to_owned());
+    }
+    for path in spec.paths.iter().filter(|p| !p.starts_with('/')) {
+        problems.push(format!("paths: {path:?} is not an absolute path"));
+    }
+
+    let ignored = [
+        ("statefulSet", spec.backup.stateful_set.is_some()),
+        ("quiesce", spec.backup.quiesce.is_some()),
+        ("resources", spec.backup.resources.is_some()),
+    ];
+    for (field, _) in ignored.iter().filter(|(_, set)| *set) {
+        problems.push(format!("backup.{field} is not supported by node backups"));
+    }
+
+    if let Err(err) = node::validate(backup) {
+        problems.push(err.to_string());
+    }
+    problems.extend(self::backup(&node::node_spec(spec)));
+    problems
+}
+
+fn has_keep_policy(retention: &Retention) -> bool {
+    let counts = [
+        retention.keep_last,
+        retention.keep_hourly,
+        retention.keep_daily,
+        retention.keep_weekly,
+        retention.keep_monthly,
+        retention.keep_yearly,
+    ];
+    counts.iter().any(Option::is_some)
+        || keep_within(retention).any(|(_, d)| d.is_some())
+        || retention.keep_tag.is_some()
+}
+
+/// The `keepWithin*` durations of a retention policy with their field names.
+fn keep_within(retention: &Retention) -> impl Iterator<Item = (&'static str, Option<&String>)> {
+    [
+        ("keepWithin", retention.keep_within.as_ref()),
+        ("keepWithinHourly", retention.keep_within_hourly.as_ref()),
+        ("keepWithinDaily", retention.keep_within_daily.as_ref()),
+        ("keepWithinWeekly", retention.keep_within_weekly.as_ref()),
+        ("keepWithinMonthly", retention.keep_within_monthly.as_ref()),
+        ("keepWithinYearly", retention.keep_within_yearly.as_ref()),
+    ]
+    .into_iter()
+}
+
+/// Checks a restic duration such as "2y5m7d3h".
+fn is_valid_duration(duration: &str) -> bool {
+    let mut digits = 0;
+    for b in duration.bytes() {
+        match b {
+            b'0'..=b'9' => digits += 1,
+            b'y' | b'm' | b'd' | b'h' if digits > 0 => digits = 0,
+            _ => return false,
+        }
+    }
+    !duration.is_empty() && digits == 0
+}
+
+/// Checks a restic `--group-by` value.
+fn is_valid_group_by(group_by: &str) -> bool {
+    group_by.is_empty()
+        || group_by
+            .split(',')
+            .all(|g| matches!(g, "host" | "paths" | "tags"))
+}
+
+/// Checks an RFC 1123 label, as used in container names.
+fn is_dns_label(name: &str) -> bool {
+    let valid_chars = name
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+    valid_chars && !name.is_empty() && !name.starts_with('-') && !name.ends_with('-')
+}
+
+/// Whether the size is accepted by restic, e.g. `1024`, `500k` or `2G`.
+fn is_valid_size(size: &str) -> bool {
+    let digits = size
+        .strip_suffix(['k', 'K', 'm', 'M', 'g', 'G', 't', 'T'])
+        .unwrap_or(size);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{Container, Volume, VolumeMount};
+    use restic_crd::{
+        BackupOptions, BackupProfile, NodeBackupSpec, PodFailurePolicy, Repository, RepositoryType,
+        ResticConfig, ResticProfileConfig, ScheduledBackupSpec, SecondaryRepository, VolumeBackup,
+    };
+
+    use super::*;
+
+    fn create_backup() -> BackupSpec {
+        BackupSpec::builder()
+            .restic(
+                ResticConfig::builder()
+                    .repository(
+                        Repository::builder()
+                            .r#type(RepositoryType::Rest)
+                            .uri("https://example.com".to_owned())
+                            .password(Default::default())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .volume(VolumeBackup {
+                mounts: vec![VolumeMount {
+                    name: "data".to_owned(),
+                    mount_path: "/data".to_owned(),
+                    ..Default::default()
+                }],
+                volumes: vec![Volume {
+                    name: "data".to_owned(),
+                    ..Default::default()
+                }],
+                read_only: None,
+                tags: None,
+                path_prefix: None,
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(backup(&create_backup()).is_empty());
+    }
+
+    #[test]
+    fn test_mounts() {
+        let mut spec = create_backup();
+        spec.volume = None;
+        assert_eq!(backup(&spec).len(), 1);
+
+        let mut spec = create_backup();
+        spec.volume.as_mut().unwrap().mounts[0].name = "other".to_owned();
+        assert_eq!(
+            backup(&spec),
+            vec![r#"volume.mounts: no volume named "other" in volume.volumes"#]
+        );
+    }
+
+    #[test]
+    fn test_volume_tags_and_prefix() {
+        let mut spec = create_backup();
+        let volume = spec.volume.as_mut().unwrap();
+        volume.tags = Some([("other".to_owned(), vec!["db".to_owned()])].into());
+        volume.path_prefix = Some("data".to_owned());
+        assert_eq!(
+            backup(&spec),
+            vec![
+                r#"volume.tags: no mount named "other" in volume.mounts"#,
+                r#"volume.pathPrefix: "data" must be an absolute path"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut spec = create_backup();
+        let profile = |name: &str, paths: &[&str]| {
+            BackupProfile::builder()
+                .name(name.to_owned())
+                .paths(paths.iter().map(|p| (*p).to_owned()).collect())
+                .build()
+        };
+        spec.restic.profiles = Some(vec![
+            profile("media", &["/data/media"]),
+            profile("config", &["/data/config"]),
+        ]);
+        spec.restic.groups = Some(BTreeMap::from([(
+            "small".to_owned(),
+            vec!["default".to_owned(), "config".to_owned()],
+        )]));
+        assert!(backup(&spec).is_empty());
+
+        spec.restic.profiles = Some(vec![
+            profile("media", &[]),
+            profile("media", &["/data/media"]),
+            profile("mount-data", &["/data"]),
+        ]);
+        spec.restic.groups = Some(BTreeMap::from([(
+            "all".to_owned(),
+            vec!["config".to_owned()],
+        )]));
+        assert_eq!(
+            backup(&spec),
+            vec![
+                r#"restic.profiles: profile "media" has no paths"#,
+                r#"restic.profiles: profile name "media" is used more than once"#,
+                r#"restic.profiles: profile name "mount-data" is reserved"#,
+                r#"restic.groups: group name "all" is reserved"#,
+                r#"restic.groups: group "all" has unknown profile "config""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_secondary_repositories() {
+        let mut spec = create_backup();
+        let repo = |name: &str| {
+            SecondaryRepository::builder()
+                .name(name.to_owned())
+                .uri("https://offsite.example.com".to_owned())
+                .password(Default::default())
+                .build()
+        };
+        spec.restic.secondary_repositories = Some(vec![repo("offsite"), repo("nas")]);
+        assert!(backup(&spec).is_empty());
+
+        spec.restic.secondary_repositories =
+            Some(vec![repo("offsite"), repo("offsite"), repo("Off_site")]);
+        spec.restic_profile = Some(ResticProfileConfig {
+            extra_containers: Some(vec![Container {
+                name: "restic-copy-offsite".to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        });
+        assert_eq!(
+            backup(&spec),
+            vec![
+                r#"restic.secondaryRepositories: name "offsite" is used more than once"#,
+                r#"restic.secondaryRepositories: name "Off_site" must be a DNS label of at most 51 characters"#,
+                "resticProfile.extraContainers cannot be used with restic.secondaryRepositories, use init containers with restartPolicy: Always for sidecars",
+                r#"resticProfile: container name "restic-copy-offsite" is used more than once"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut spec = create_backup();
+        spec.restic.compression = Compression::Max;
+        assert!(backup(&spec).is_empty());
+
+        spec.restic.repository.version = Some(1);
+        assert_eq!(
+            backup(&spec),
+            vec!["restic.compression: max requires repository format version 2"]
+        );
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut spec = create_backup();
+        spec.restic.retention = Some(
+            Retention::builder()
+                .after_backup(true)
+                .before_backup(false)
+                .prune(true)
+                .build(),
+        );
+        assert_eq!(backup(&spec).len(), 1);
+
+        spec.restic.retention.as_mut().unwrap().keep_daily = Some(7);
+        assert!(backup(&spec).is_empty());
+
+        let retention = spec.restic.retention.as_mut().unwrap();
+        retention.keep_daily = None;
+        retention.keep_within = Some("1y6m".to_owned());
+        assert!(backup(&spec).is_empty());
+
+        let retention = spec.restic.retention.as_mut().unwrap();
+        retention.keep_within_daily = Some("7 days".to_owned());
+        retention.group_by = Some("host,files".to_owned());
+        assert_eq!(backup(&spec).len(), 2);
+    }
+
+    #[test]
+    fn test_is_valid_duration() {
+        assert!(is_valid_duration("2y5m7d3h"));
+        assert!(is_valid_duration("30d"));
+        assert!(!is_valid_duration(""));
+        assert!(!is_valid_duration("7"));
+        assert!(!is_valid_duration("d"));
+        assert!(!is_valid_duration("1w"));
+    }
+
+    #[test]
+    fn test_exclude_larger_than() {
+        let mut spec = create_backup();
+        spec.restic.backup = Some(
+            BackupOptions::builder()
+                .exclude_caches(false)
+                .exclude_larger_than("1.5G".to_owned())
+                .build(),
+        );
+        assert_eq!(backup(&spec).len(), 1);
+
+        for size in ["1024", "500k", "2G", "1t"] {
+            assert!(is_valid_size(size), "{size}");
+        }
+        for size in ["", "G", "1 G", "1GB", "-1"] {
+            assert!(!is_valid_size(size), "{size}");
+        }
+    }
+
+    #[test]
+    fn test_exclude_cloud_files() {
+        let mut spec = create_backup();
+        spec.restic.backup = Some(
+            BackupOptions::builder()
+                .exclude_caches(false)
+                .exclude_cloud_files(true)
+                .build(),
+        );
+        assert_eq!(backup(&spec).len(), 1);
+    }
+
+    #[test]
+    fn test_container_names() {
+        let container = |name: &str| Container {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        let mut spec = create_backup();
+        spec.restic_profile = Some(ResticProfileConfig {
+            init_containers: Some(vec![container("init")]),
+            extra_containers: Some(vec![container("sidecar")]),
+            ..Default::default()
+        });
+        assert!(backup(&spec).is_empty());
+
+        let rpcfg = spec.restic_profile.as_mut().unwrap();
+        rpcfg.init_containers = Some(vec![container("sidecar"), container("restic-backup")]);
+        assert_eq!(backup(&spec).len(), 2);
+    }
+
+    #[test]
+    fn test_pod_failure_policy() {
+        let mut spec = create_backup();
+        spec.pod_failure_policy = Some(PodFailurePolicy {
+            fail_job_exit_codes: Some(vec![3]),
+            ..Default::default()
+        });
+        assert!(backup(&spec).is_empty());
+
+        spec.restic_profile = Some(ResticProfileConfig {
+            restart_policy: Some(RestartPolicy::OnFailure),
+            ..Default::default()
+        });
+        spec.pod_failure_policy.as_mut().unwrap().ignore_exit_codes = Some(vec![0]);
+        assert_eq!(backup(&spec).len(), 2);
+    }
+
+    #[test]
+    fn test_node_backup() {
+        let spec = NodeBackupSpec::builder()
+            .paths(vec!["/etc".to_owned()])
+            .backup(create_backup())
+            .build();
+        let mut backup = NodeBackup::new("test", spec);
+        assert!(node_backup(&backup).is_empty());
+
+        backup.spec.paths.push("etc".to_owned());
+        backup.spec.schedule = Some("0 2 * *".to_owned());
+        assert_eq!(node_backup(&backup).len(), 2);
+    }
+
+    #[test]
+    fn test_scheduled_backup() {
+        let spec = ScheduledBackupSpec::builder()
+            .schedule("0 2 * *".to_owned())
+            .backup(create_backup())
+            .build();
+        let backup = ScheduledBackup::new("test", spec);
+        assert_eq!(scheduled_backup(&backup).len(), 1);
+    }
+}
@@ -2,11 +2,22 @@ use std::io::Write;
 
 use cargo_metadata::MetadataCommand;
 use clap::{Parser, Subcommand};
-use kube::CustomResourceExt;
-use restic_crd::{Backup, ScheduledBackup};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+    CustomResourceConversion, CustomResourceDefinition, ServiceReference, WebhookClientConfig,
+    WebhookConversion,
+};
+use kube::{core::crd::merge_crds, CustomResourceExt};
+use restic_crd::{v1beta1, Backup, NodeBackup, ScheduledBackup};
 
 const PACKAGE_NAME: &str = "restic-operator";
 
+/// API version objects are stored as
+const STORAGE_VERSION: &str = "v1alpha1";
+/// Service of the operator's webhook server, see `examples/webhook.yaml`
+const WEBHOOK_SERVICE: &str = "restic-operator-webhook";
+const WEBHOOK_NAMESPACE: &str = "restic-system";
+const CONVERT_PATH: &str = "/convert";
+
 #[derive(Debug, Parser)]
 struct Args {
     #[clap(subcommand)]
@@ -32,8 +43,14 @@ fn generate_crd() {
     std::fs::create_dir_all("crds").unwrap();
 
     // Generate CRD YAML
-    let backup_crd = serde_yaml::to_string(&Backup::crd()).unwrap();
-    let scheduled_backup_crd = serde_yaml::to_string(&ScheduledBackup::crd()).unwrap();
+    let backup_crd = multi_version_crd(vec![Backup::crd(), v1beta1::Backup::crd()]);
+    let scheduled_backup_crd = multi_version_crd(vec![
+        ScheduledBackup::crd(),
+        v1beta1::ScheduledBackup::crd(),
+    ]);
+    let backup_crd = serde_yaml::to_string(&backup_crd).unwrap();
+    let scheduled_backup_crd = serde_yaml::to_string(&scheduled_backup_crd).unwrap();
+    let node_backup_crd = serde_yaml::to_string(&NodeBackup::crd()).unwrap();
 
     // Get operator version from Cargo.toml
     let metadata = MetadataCommand::new()
@@ -56,6 +73,36 @@ fn generate_crd() {
     file.write_all(backup_crd.as_bytes()).unwrap();
     file.write_all(b"---\n").unwrap();
     file.write_all(scheduled_backup_crd.as_bytes()).unwrap();
+    file.write_all(b"---\n").unwrap();
+    file.write_all(node_backup_crd.as_bytes()).unwrap();
     file.flush().unwrap();
     eprintln!("Done");
 }
+
+/// Merges the versions of a CRD, converting between them with the operator's webhook.
+fn multi_version_crd(crds: Vec<CustomResourceDefinition>) -> CustomResourceDefinition {
+    let mut crd = merge_crds(crds, STORAGE_VERSION).expect("CRD versions should be mergeable");
+    crd.spec.conversion = Some(CustomResourceConversion {
+        strategy: "Webhook".to_owned(),
+        webhook: Some(WebhookConversion {
+            client_config: Some(WebhookClientConfig {
+                service: Some(ServiceReference {
+                    name: WEBHOOK_SERVICE.to_owned(),
+                    namespace: WEBHOOK_NAMESPACE.to_owned(),
+                    path: Some(CONVERT_PATH.to_owned()),
+                    port: None,
+                }),
+                ..WebhookClientConfig::default()
+            }),
+            conversion_review_versions: vec!["v1".to_owned()],
+        }),
+    });
+    crd.metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(
+            "cert-manager.io/inject-ca-from".to_owned(),
+            format!("{WEBHOOK_NAMESPACE}/{WEBHOOK_SERVICE}"),
+        );
+    crd
+}